@@ -0,0 +1,93 @@
+//! A language server for akari templates, speaking the Language Server Protocol over stdio.
+//!
+//! Reports problems found by the tokenizer, the template analysis and the compiler as
+//! diagnostics, resolves `template`/`insert`/`load` paths and block names for go-to-definition,
+//! shows the override chain of a block on hover and completes directive keywords.
+//! Templates are named relative to the workspace root, like `TemplateManager::new(root)` would.
+use std::io::{self, BufReader};
+use std::process::exit;
+
+use akari::Value;
+
+mod protocol;
+mod server;
+
+use protocol::{dict, error_response, notification, read_message, response, write_message};
+use server::Server;
+
+/// The JSON-RPC error code of an unknown method
+const METHOD_NOT_FOUND: i32 = -32601;
+
+fn main() {
+    let mut reader = BufReader::new(io::stdin().lock());
+    let mut writer = io::stdout().lock();
+    let mut server = Server::default();
+    let mut shutdown = false;
+
+    loop {
+        let message = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("akari-lsp: {}", err);
+                exit(1);
+            }
+        };
+        let method = message.get("method").string();
+        let params = message.get("params");
+        let id = message.get("id");
+        let document = params.get("textDocument").get("uri").string();
+
+        let mut replies = Vec::new();
+        // Documents whose diagnostics must be published again after this message
+        let mut changed = false;
+        match method.as_str() {
+            "initialize" => replies.push(response(id, server.initialize(params))),
+            "shutdown" => {
+                shutdown = true;
+                replies.push(response(id, Value::None));
+            },
+            "exit" => exit(if shutdown { 0 } else { 1 }),
+            "textDocument/didOpen" => {
+                server.open(&document, params.get("textDocument").get("text").string());
+                changed = true;
+            },
+            "textDocument/didChange" => {
+                // Full document sync, so the last change holds the whole text
+                if let Value::List(changes) = params.get("contentChanges")
+                    && let Some(last) = changes.last() {
+                    server.open(&document, last.get("text").string());
+                    changed = true;
+                }
+            },
+            "textDocument/didSave" => changed = true,
+            "textDocument/didClose" => {
+                server.close(&document);
+                let params = dict([("uri", Value::new(document.as_str())), ("diagnostics", Value::List(Vec::new()))]);
+                replies.push(notification("textDocument/publishDiagnostics", params));
+                changed = true;
+            },
+            "textDocument/definition" => replies.push(response(id, server.definition(&document, params.get("position")))),
+            "textDocument/hover" => replies.push(response(id, server.hover(&document, params.get("position")))),
+            "textDocument/completion" => replies.push(response(id, server.completion(&document, params.get("position")))),
+            // Other notifications, such as `initialized`, need no answer
+            _ if id.is_none() => {},
+            _ => replies.push(error_response(id, METHOD_NOT_FOUND, &format!("Unknown method '{}'", method))),
+        }
+
+        // A change can break or fix the templates that extend or insert the changed one
+        if changed {
+            for uri in server.open_documents() {
+                let params = dict([("uri", Value::new(uri.as_str())), ("diagnostics", server.diagnostics(&uri))]);
+                replies.push(notification("textDocument/publishDiagnostics", params));
+            }
+        }
+        for reply in replies {
+            if let Err(err) = write_message(&mut writer, &reply) {
+                eprintln!("akari-lsp: {}", err);
+                exit(1);
+            }
+        }
+    }
+    exit(if shutdown { 0 } else { 1 });
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use akari::Value;
+
+/// Reads one message framed by a `Content-Length` header. Returns None at the end of input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Value::from_json(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes one message with its `Content-Length` header
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.into_json();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Builds a JSON object
+pub fn dict<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Dict(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect::<HashMap<_, _>>())
+}
+
+/// The response to a request
+pub fn response(id: &Value, result: Value) -> Value {
+    dict([("jsonrpc", Value::new("2.0")), ("id", id.clone()), ("result", result)])
+}
+
+/// The error response to a request
+pub fn error_response(id: &Value, code: i32, message: &str) -> Value {
+    let error = dict([("code", Value::new(code)), ("message", Value::new(message))]);
+    dict([("jsonrpc", Value::new("2.0")), ("id", id.clone()), ("error", error)])
+}
+
+/// A notification from the server
+pub fn notification(method: &str, params: Value) -> Value {
+    dict([("jsonrpc", Value::new("2.0")), ("method", Value::new(method)), ("params", params)])
+}
+
+/// An LSP position: a 0-based line and a column counted in UTF-16 code units
+pub fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    dict([("line", Value::new(line)), ("character", Value::new(character))])
+}
+
+/// An LSP range covering the bytes `start..end` of `text`
+pub fn range(text: &str, start: usize, end: usize) -> Value {
+    dict([("start", position(text, start)), ("end", position(text, end))])
+}
+
+/// The byte offset of an LSP position, clamped to the text
+pub fn offset(text: &str, position: &Value) -> usize {
+    let line = position.get("line").integer().max(0) as usize;
+    let character = position.get("character").integer().max(0) as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return text.len(),
+        }
+    };
+    let mut units = 0;
+    for (index, ch) in text[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return line_start + index;
+        }
+        units += ch.len_utf16();
+    }
+    text.len()
+}
+
+/// The path of a `file://` URI
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.bytes();
+    while let Some(byte) = rest.next() {
+        if byte == b'%' {
+            let hex: String = rest.by_ref().take(2).map(char::from).collect();
+            bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// The `file://` URI of a path
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
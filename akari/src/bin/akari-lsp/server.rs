@@ -0,0 +1,430 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use akari::{get_full_dir, tokenize_with_spans, Delimiters, ErrorPolicy, TemplateAnalysis, TemplateManager, Token, Value};
+
+use crate::protocol::{dict, offset, path_to_uri, range, uri_to_path};
+
+/// LSP diagnostic severities
+const ERROR: i32 = 1;
+const WARNING: i32 = 2;
+const INFORMATION: i32 = 3;
+
+/// The LSP completion item kind of keywords
+const KEYWORD_KIND: i32 = 14;
+/// The LSP completion item kind of block names
+const MODULE_KIND: i32 = 9;
+
+/// Keywords starting a directive, offered by completion
+const KEYWORDS: [&str; 23] = [
+    "template", "insert", "block", "endblock", "placeholder", "export", "let", "output", "del",
+    "if", "endif", "for", "endfor", "while", "endwhile", "trans", "cache", "endcache",
+    "verbatim", "endverbatim", "assert", "debug", "load",
+];
+
+/// A token paired with the source bytes it was read from
+type Spanned = (Token, Range<usize>);
+
+/// The state of the language server: the workspace root and the open documents
+#[derive(Default)]
+pub struct Server {
+    /// Templates are named relative to this directory, or to their own directory without one
+    root: Option<PathBuf>,
+    /// The text of every open document by URI, which takes precedence over the file on disk
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    /// Answers `initialize`, taking the workspace root from the client
+    pub fn initialize(&mut self, params: &Value) -> Value {
+        let root_uri = match params.get("rootUri") {
+            Value::Str(uri) => Some(uri.clone()),
+            _ => match params.get("workspaceFolders") {
+                Value::List(folders) => folders.first().map(|folder| folder.get("uri").string()),
+                _ => None,
+            },
+        };
+        self.root = root_uri.as_deref().and_then(uri_to_path).or_else(|| match params.get("rootPath") {
+            Value::Str(path) => Some(PathBuf::from(path)),
+            _ => None,
+        });
+
+        let capabilities = dict([
+            ("textDocumentSync", Value::new(1)),
+            ("definitionProvider", Value::new(true)),
+            ("hoverProvider", Value::new(true)),
+            ("completionProvider", dict([("triggerCharacters", Value::List(vec![Value::new(" ")]))])),
+        ]);
+        let server_info = dict([("name", Value::new("akari-lsp")), ("version", Value::new(env!("CARGO_PKG_VERSION")))]);
+        dict([("capabilities", capabilities), ("serverInfo", server_info)])
+    }
+
+    pub fn open(&mut self, uri: &str, text: String) {
+        self.documents.insert(uri.to_string(), text);
+    }
+
+    pub fn close(&mut self, uri: &str) {
+        self.documents.remove(uri);
+    }
+
+    /// URIs of the open documents
+    pub fn open_documents(&self) -> Vec<String> {
+        self.documents.keys().cloned().collect()
+    }
+
+    /// The template directory and template name of a document
+    fn template_of(&self, uri: &str) -> Option<(PathBuf, String)> {
+        let path = uri_to_path(uri)?;
+        if let Some(root) = &self.root
+            && let Ok(relative) = path.strip_prefix(root) {
+            return Some((root.clone(), template_name(relative)));
+        }
+        let directory = path.parent()?.to_path_buf();
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        Some((directory, name))
+    }
+
+    /// A manager over `directory` that reads open documents instead of their files
+    fn manager(&self, directory: &Path) -> TemplateManager {
+        let open = self.documents.iter().filter_map(|(uri, text)| {
+            let path = uri_to_path(uri)?;
+            let relative = path.strip_prefix(directory).ok()?;
+            Some((template_name(relative), text.clone()))
+        });
+        TemplateManager::new(directory)
+            .with_caching(false)
+            .with_embedded_templates(open.collect::<Vec<_>>())
+    }
+
+    /// The source of a template, open or on disk
+    fn source_of(&self, path: &Path) -> Option<String> {
+        let uri = path_to_uri(path);
+        self.documents.get(&uri).cloned().or_else(|| fs::read_to_string(path).ok())
+    }
+
+    /// Problems found in a document, from the tokenizer, the analysis and the compiler
+    pub fn diagnostics(&self, uri: &str) -> Value {
+        let Some(text) = self.documents.get(uri) else { return Value::List(Vec::new()) };
+        let delimiters = Delimiters::default();
+        let tokens = tokenize_with_spans(text, &delimiters);
+        let mut diagnostics = structural_diagnostics(text, &tokens, &delimiters);
+
+        // A template that does not even tokenize cleanly would only produce follow-up errors
+        if diagnostics.iter().any(|diagnostic| diagnostic.get("severity").integer() == ERROR as i64) {
+            return Value::List(diagnostics);
+        }
+        let Some((directory, name)) = self.template_of(uri) else { return Value::List(diagnostics) };
+        let manager = self.manager(&directory);
+
+        match manager.analyze(&name) {
+            Ok(analysis) => diagnostics.extend(analysis_diagnostics(text, &tokens, &name, &analysis, &manager)),
+            Err(message) => diagnostics.push(diagnostic(text, 0..0, ERROR, &message)),
+        }
+        let manager = manager.with_error_policy(ErrorPolicy::callback(|_| {}));
+        if let Err(message) = manager.render(&name, &HashMap::new()) {
+            diagnostics.push(diagnostic(text, 0..0, ERROR, &message));
+        }
+        Value::List(diagnostics)
+    }
+
+    /// Where the template path or block name under the cursor is defined
+    pub fn definition(&self, uri: &str, position: &Value) -> Value {
+        let Some(text) = self.documents.get(uri) else { return Value::None };
+        let Some((directory, name)) = self.template_of(uri) else { return Value::None };
+        let tokens = tokenize_with_spans(text, &Delimiters::default());
+        let Some(index) = token_at(&tokens, offset(text, position)) else { return Value::None };
+        let manager = self.manager(&directory);
+
+        match (index.checked_sub(1).map(|previous| &tokens[previous].0), &tokens[index].0) {
+            (Some(Token::TemplateKeyword | Token::InsertKeyword | Token::LoadKeyword), Token::Object(Value::Str(path))) => {
+                let file = manager.get_template_path(&get_full_dir(path, &name));
+                dict([("uri", Value::new(path_to_uri(&file))), ("range", range("", 0, 0))])
+            },
+            (Some(Token::BlockKeyword | Token::PlaceholderKeyword), Token::Identifier(block)) => {
+                let Ok(analysis) = manager.analyze(&name) else { return Value::None };
+                let mut locations = Vec::new();
+                for template in chain(&analysis) {
+                    let file = manager.get_template_path(&template);
+                    let Some(source) = self.source_of(&file) else { continue };
+                    for span in section_spans(&source, block) {
+                        if template == name && span == tokens[index].1 {
+                            continue;
+                        }
+                        locations.push(dict([
+                            ("uri", Value::new(path_to_uri(&file))),
+                            ("range", range(&source, span.start, span.end)),
+                        ]));
+                    }
+                }
+                Value::List(locations)
+            },
+            _ => Value::None,
+        }
+    }
+
+    /// The override chain of the block under the cursor, or the file a template path resolves to
+    pub fn hover(&self, uri: &str, position: &Value) -> Value {
+        let Some(text) = self.documents.get(uri) else { return Value::None };
+        let Some((directory, name)) = self.template_of(uri) else { return Value::None };
+        let tokens = tokenize_with_spans(text, &Delimiters::default());
+        let Some(index) = token_at(&tokens, offset(text, position)) else { return Value::None };
+        let manager = self.manager(&directory);
+
+        let contents = match (index.checked_sub(1).map(|previous| &tokens[previous].0), &tokens[index].0) {
+            (Some(Token::TemplateKeyword | Token::InsertKeyword | Token::LoadKeyword), Token::Object(Value::Str(path))) => {
+                let full = get_full_dir(path, &name);
+                let file = manager.get_template_path(&full);
+                let mut contents = format!("`{}`\n\n{}", full, file.display());
+                if self.source_of(&file).is_none() {
+                    contents.push_str("\n\n*File not found*");
+                }
+                contents
+            },
+            (Some(Token::BlockKeyword | Token::PlaceholderKeyword), Token::Identifier(block)) => {
+                let Ok(analysis) = manager.analyze(&name) else { return Value::None };
+                block_hover(block, &analysis)
+            },
+            _ => return Value::None,
+        };
+        let span = &tokens[index].1;
+        dict([
+            ("contents", dict([("kind", Value::new("markdown")), ("value", Value::new(contents))])),
+            ("range", range(text, span.start, span.end)),
+        ])
+    }
+
+    /// Keywords, with the closer of the innermost open section first, or block names after `block`
+    pub fn completion(&self, uri: &str, position: &Value) -> Value {
+        let Some(text) = self.documents.get(uri) else { return Value::List(Vec::new()) };
+        let cursor = offset(text, position);
+        let tokens = tokenize_with_spans(text, &Delimiters::default());
+        let before: Vec<&Spanned> = tokens.iter().filter(|(_, span)| span.end <= cursor).collect();
+
+        if let Some((Token::BlockKeyword | Token::PlaceholderKeyword, _)) = before.last()
+            && let Some((directory, name)) = self.template_of(uri)
+            && let Ok(analysis) = self.manager(&directory).analyze(&name) {
+            let mut names: BTreeSet<&String> = analysis.blocks.keys().collect();
+            names.extend(analysis.unresolved_placeholders.iter());
+            let items = names.into_iter()
+                .map(|block| dict([("label", Value::new(block.as_str())), ("kind", Value::new(MODULE_KIND))]))
+                .collect();
+            return Value::List(items);
+        }
+
+        let mut open = Vec::new();
+        for (token, _) in directive_starts(&tokens).filter(|(_, span)| span.end <= cursor) {
+            match section(token) {
+                Some((_, closer, true)) => open.push(closer),
+                Some((_, closer, false)) if open.last() == Some(&closer) => {
+                    open.pop();
+                },
+                _ => {},
+            }
+        }
+        let expected = open.last().copied();
+        let items = KEYWORDS.iter().map(|keyword| {
+            let preselect = Some(*keyword) == expected;
+            let sort_text = if preselect { "0".to_string() } else { format!("1{}", keyword) };
+            dict([
+                ("label", Value::new(*keyword)),
+                ("kind", Value::new(KEYWORD_KIND)),
+                ("sortText", Value::new(sort_text)),
+                ("preselect", Value::new(preselect)),
+            ])
+        });
+        Value::List(items.collect())
+    }
+}
+
+/// A template name with `/` separators from a path relative to the template directory
+fn template_name(relative: &Path) -> String {
+    relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn diagnostic(text: &str, span: Range<usize>, severity: i32, message: &str) -> Value {
+    dict([
+        ("range", range(text, span.start, span.end)),
+        ("severity", Value::new(severity)),
+        ("source", Value::new("akari")),
+        ("message", Value::new(message)),
+    ])
+}
+
+/// The index of the token whose span holds the cursor, or ends right at it
+fn token_at(tokens: &[Spanned], cursor: usize) -> Option<usize> {
+    tokens.iter().position(|(token, span)| {
+        !matches!(token, Token::HtmlContent(_) | Token::EndOfStatement) && span.start <= cursor && cursor <= span.end
+    })
+}
+
+/// The first token of every directive
+fn directive_starts(tokens: &[Spanned]) -> impl Iterator<Item = &Spanned> {
+    tokens.iter().enumerate()
+        .filter(|(i, (token, _))| {
+            !matches!(token, Token::HtmlContent(_) | Token::EndOfStatement)
+                && (*i == 0 || matches!(tokens[i - 1].0, Token::HtmlContent(_) | Token::EndOfStatement))
+        })
+        .map(|(_, spanned)| spanned)
+}
+
+/// The opening and closing keywords of the section a token opens (true) or closes (false)
+fn section(token: &Token) -> Option<(&'static str, &'static str, bool)> {
+    match token {
+        Token::IfKeyword => Some(("if", "endif", true)),
+        Token::ForKeyword => Some(("for", "endfor", true)),
+        Token::WhileKeyword => Some(("while", "endwhile", true)),
+        Token::BlockKeyword => Some(("block", "endblock", true)),
+        Token::CacheKeyword => Some(("cache", "endcache", true)),
+        Token::EndIfKeyword => Some(("if", "endif", false)),
+        Token::EndForKeyword => Some(("for", "endfor", false)),
+        Token::EndWhileKeyword => Some(("while", "endwhile", false)),
+        Token::EndBlockKeyword => Some(("block", "endblock", false)),
+        Token::EndCacheKeyword => Some(("cache", "endcache", false)),
+        _ => None,
+    }
+}
+
+/// Unclosed directives, unterminated strings, unbalanced sections and unknown characters
+fn structural_diagnostics(text: &str, tokens: &[Spanned], delimiters: &Delimiters) -> Vec<Value> {
+    let mut diagnostics = Vec::new();
+    let mut directive_start = None;
+    for (token, span) in tokens {
+        if directive_start.is_none() && !matches!(token, Token::HtmlContent(_)) {
+            // The opening marker sits before the first token, possibly separated by whitespace
+            directive_start = text[..span.start].rfind(delimiters.open.as_str());
+        }
+        match token {
+            Token::EndOfStatement => {
+                if span.is_empty() {
+                    let start = directive_start.unwrap_or(span.start);
+                    let message = format!("Unclosed directive, expected `{}`", delimiters.close);
+                    diagnostics.push(diagnostic(text, start..span.end, ERROR, &message));
+                }
+                directive_start = None;
+            },
+            // A string without its closing quote runs to the end of the input
+            Token::Object(Value::Str(_)) | Token::InterpolatedString(_) if span.end == text.len() => {
+                diagnostics.push(diagnostic(text, span.clone(), ERROR, "Unterminated string literal"));
+            },
+            Token::Error(message) => diagnostics.push(diagnostic(text, span.clone(), ERROR, message)),
+            Token::HtmlContent(_) if text[span.clone()].starts_with(delimiters.open.as_str()) => {
+                let region = text[span.clone()].trim_end();
+                let closed = region.strip_suffix(delimiters.close.as_str())
+                    .is_some_and(|rest| rest.trim_end().ends_with("endverbatim"));
+                if !closed {
+                    let end = span.start + text[span.clone()].find(delimiters.close.as_str()).map_or(0, |close| close + delimiters.close.len());
+                    diagnostics.push(diagnostic(text, span.start..end, ERROR, "`verbatim` is never closed, expected `endverbatim`"));
+                }
+            },
+            Token::Identifier(name) if !name.starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+                let message = format!("Unexpected character `{}`", name);
+                diagnostics.push(diagnostic(text, span.clone(), WARNING, &message));
+            },
+            _ => {},
+        }
+    }
+
+    // Sections must close in the order they were opened
+    let mut open: Vec<(&str, &str, Range<usize>)> = Vec::new();
+    for (token, span) in directive_starts(tokens) {
+        match section(token) {
+            Some((opener, closer, true)) => open.push((opener, closer, span.clone())),
+            Some((opener, closer, false)) => match open.iter().rposition(|(_, expected, _)| *expected == closer) {
+                Some(matching) => {
+                    // Sections opened inside the one closed here are reported once, at the closer
+                    if let Some((last, expected, _)) = open.last()
+                        && *expected != closer {
+                        let message = format!("`{}` found while `{}` is still open, expected `{}`", closer, last, expected);
+                        diagnostics.push(diagnostic(text, span.clone(), ERROR, &message));
+                    }
+                    open.truncate(matching);
+                },
+                None => {
+                    let message = format!("`{}` without a matching `{}`", closer, opener);
+                    diagnostics.push(diagnostic(text, span.clone(), ERROR, &message));
+                },
+            },
+            None => {},
+        }
+    }
+    for (opener, closer, span) in open {
+        let message = format!("`{}` is never closed, expected `{}`", opener, closer);
+        diagnostics.push(diagnostic(text, span, ERROR, &message));
+    }
+    diagnostics
+}
+
+/// Missing templates and data files, dead blocks and unresolved placeholders, placed on
+/// the directives of this document that cause them
+fn analysis_diagnostics(text: &str, tokens: &[Spanned], name: &str, analysis: &TemplateAnalysis, manager: &TemplateManager) -> Vec<Value> {
+    let mut diagnostics = Vec::new();
+    for pair in tokens.windows(2) {
+        match (&pair[0].0, &pair[1].0) {
+            (Token::TemplateKeyword | Token::InsertKeyword, Token::Object(Value::Str(path))) => {
+                let full = get_full_dir(path, name);
+                if analysis.missing_templates.contains(&full) {
+                    let message = format!("Template '{}' not found", full);
+                    diagnostics.push(diagnostic(text, pair[1].1.clone(), ERROR, &message));
+                }
+            },
+            (Token::LoadKeyword, Token::Object(Value::Str(path))) => {
+                if let Err(message) = manager.load_data(&get_full_dir(path, name)) {
+                    diagnostics.push(diagnostic(text, pair[1].1.clone(), ERROR, &message));
+                }
+            },
+            (Token::BlockKeyword, Token::Identifier(block)) if analysis.dead_blocks.contains(block) => {
+                let message = format!("Block '{}' is never rendered: no parent template has a slot for it", block);
+                diagnostics.push(diagnostic(text, pair[1].1.clone(), WARNING, &message));
+            },
+            (Token::PlaceholderKeyword, Token::Identifier(block)) if analysis.unresolved_placeholders.contains(block) => {
+                let message = format!("No block fills placeholder '{}' when this template is rendered itself", block);
+                diagnostics.push(diagnostic(text, pair[1].1.clone(), INFORMATION, &message));
+            },
+            _ => {},
+        }
+    }
+    diagnostics
+}
+
+/// The analyzed template and its ancestors, root ancestor first
+fn chain(analysis: &TemplateAnalysis) -> Vec<String> {
+    let mut chain: Vec<String> = analysis.parent_chain.iter().rev().cloned().collect();
+    chain.push(analysis.name.clone());
+    chain
+}
+
+/// Spans of the names of `block name` and `placeholder name` directives in a source
+fn section_spans(source: &str, block: &str) -> Vec<Range<usize>> {
+    tokenize_with_spans(source, &Delimiters::default())
+        .windows(2)
+        .filter(|pair| matches!(pair[0].0, Token::BlockKeyword | Token::PlaceholderKeyword))
+        .filter(|pair| matches!(&pair[1].0, Token::Identifier(name) if name == block))
+        .map(|pair| pair[1].1.clone())
+        .collect()
+}
+
+/// Markdown describing which templates define a block and which one is rendered
+fn block_hover(block: &str, analysis: &TemplateAnalysis) -> String {
+    let mut contents = format!("**block** `{}`", block);
+    match analysis.blocks.get(block) {
+        Some(definitions) => {
+            let chain: Vec<String> = definitions.iter().map(|template| format!("`{}`", template)).collect();
+            contents.push_str(&format!("\n\nDefined in: {}", chain.join(" → ")));
+            if analysis.dead_blocks.contains(block) {
+                contents.push_str("\n\nNever rendered: no parent template has a slot for it");
+            } else if let Some(rendered) = definitions.last() {
+                contents.push_str(&format!("\n\nRendered from `{}`", rendered));
+            }
+        },
+        None if analysis.unresolved_placeholders.contains(block) => {
+            contents.push_str("\n\nNo block fills this placeholder when the template is rendered itself");
+        },
+        None => {},
+    }
+    contents
+}
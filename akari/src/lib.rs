@@ -32,14 +32,30 @@ pub use object::*;
 #[cfg(feature = "template")]
 mod template; 
 #[cfg(feature = "template")]
-pub use template::parse::{Token, tokenize};
+pub use template::parse::{Token, StringPart, Delimiters, tokenize, tokenize_with, tokenize_with_locations, tokenize_with_spans};
 #[cfg(feature = "template")]
-pub use template::compile::compile;
+pub use template::compile::{compile, compile_with, Compiled, ErrorPolicy, RenderOptions};
 #[cfg(feature = "template")]
-pub use template::template_manager::TemplateManager; 
+pub use template::template_manager::{TemplateManager, get_full_dir}; 
+#[cfg(feature = "template")]
+pub use template::analysis::TemplateAnalysis; 
+#[cfg(feature = "template")]
+pub use template::context::Context; 
+#[cfg(feature = "template")]
+pub use template::i18n::{Catalog, MessageCatalog, plural_category, translatable_keys}; 
+#[cfg(feature = "template")]
+pub use template::cache::{FragmentCache, LruCache}; 
+#[cfg(feature = "template")]
+pub use template::source_map::{SourceLocation, SourceMapping, SourceMap}; 
+#[cfg(feature = "template")]
+pub use template::output::{OutputMode, OutputFormat, mode_for_extension}; 
+#[cfg(feature = "template")]
+pub use template::format::{format_template, FormatOptions}; 
 
 #[cfg(feature = "object_macro")]
 pub use akari_macro::object; 
+#[cfg(feature = "template_macro")]
+pub use akari_macro::{template, include_templates}; 
 
 #[cfg(any(feature = "extension"))]
 pub mod extensions; 
@@ -1,83 +1,470 @@
 use std::env;
 use std::fs;
+use std::io;
 use std::process::exit;
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
-use akari::TemplateManager; 
+use akari::{TemplateManager, ErrorPolicy, tokenize, translatable_keys, format_template, FormatOptions}; 
 use akari::Value; 
 
+/// Exit code of a render error, an I/O error, or unformatted files under `fmt --check`
+const EXIT_FAILURE: i32 = 1;
+/// Exit code of unknown commands and options, or missing arguments
+const EXIT_USAGE: i32 = 2;
+/// Exit code of a template that does not exist
+const EXIT_MISSING_TEMPLATE: i32 = 3;
+
+const COMMANDS: &str = "\
+Commands:
+  render <file> [--data <file.json|->]... [key=value ...]
+                      Render a template file from the template directory
+  render_string <template> [--data <file.json|->]... [key=value ...]
+                      Render a template given as text
+  extract <file|dir>...
+                      List the message keys of `trans` and `_(...)`
+  fmt [--check] [--indent <n>] <file|dir>...
+                      Format template sources in place, or list those that need it
+  build <src> <out> [--data <file.json|->]... [key=value ...]
+                      Render every page of a site directory and copy its assets";
+
 fn main() {
-    // Collect command-line arguments.
-    let args: Vec<String> = env::args().collect();
+    // Collect command-line arguments, taking out the options that apply to every command.
+    let mut program_args = env::args();
+    let program = program_args.next().unwrap_or_else(|| "akari".to_string());
+    let mut args = vec![program];
+    let mut output_file: Option<String> = None;
+    let mut template_dir = PathBuf::new();
+    while let Some(arg) = program_args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print_help(&args[0]);
+                exit(0);
+            }
+            "-V" | "--version" => {
+                println!("akari {}", env!("CARGO_PKG_VERSION"));
+                println!();
+                println!("{}", COMMANDS);
+                exit(0);
+            }
+            "-o" | "--output" => output_file = Some(option_value(&arg, program_args.next())),
+            "--template-dir" => template_dir = PathBuf::from(option_value(&arg, program_args.next())),
+            // Options of a command keep their value with them
+            "--data" | "--indent" => {
+                args.push(arg.clone());
+                args.push(option_value(&arg, program_args.next()));
+            }
+            _ => args.push(arg),
+        }
+    }
 
     // We expect at least three arguments: executable name, command, and content (or file path).
     if args.len() < 3 {
-        eprintln!("Usage: {} <command> <content|file_path> [key=value ...]", args[0]);
-        exit(1);
+        eprintln!("Usage: {} [-o <file>] [--template-dir <dir>] <command> <args>...", args[0]);
+        eprintln!("Run '{} --help' for the commands.", args[0]);
+        exit(EXIT_USAGE);
     }
 
+    // Template errors are reported on stderr, and make the command fail after the output is written
+    let mut failed = false;
     let output = match args[1].as_str() {
         "render_string" => {
             // The second argument is the content.
             let content = &args[2]; 
 
-            // Build the arguments HashMap from key=value pairs.
-            let mut context: HashMap<String, Value> = HashMap::new();
-            for arg in args.iter().skip(3) {
-                if let Some(pos) = arg.find('=') {
-                    let key = &arg[..pos];
-                    let value = &arg[pos + 1..]; 
-                    let value = Value::from_json(&value.to_string()).unwrap_or_else(|_| {
-                        eprintln!("Failed to parse value: {}", key);
-                        exit(1);
-                    });  
-                    context.insert(key.to_string(), value);
-                } else {
-                    eprintln!("Warning: ignoring malformed argument '{}'. Expected key=value.", arg);
-                }
-            }
+            // Build the context from --data files and key=value pairs.
+            let context = build_context(&args[3..]);
 
-            let template_manager = TemplateManager::new(""); 
-            template_manager.render_string(content.clone(), &context) 
+            let (template_manager, errors) = reporting_manager(&template_dir); 
+            let output = template_manager.render_string(content.clone(), &context) 
                 .unwrap_or_else(|err| {
                     eprintln!("Failed to render template: {}", err);
-                    exit(1);
-                }) 
+                    exit(EXIT_FAILURE);
+                }); 
+            failed = report_errors(&errors);
+            output
         }
         "render" => { 
             // The second argument is the file path.
             let file_path = &args[2]; 
 
-            // Build the arguments HashMap from key=value pairs.
-            let mut context: HashMap<String, Value> = HashMap::new();
-            for arg in args.iter().skip(3) {
-                if let Some(pos) = arg.find('=') {
-                    let key = &arg[..pos];
-                    let value = &arg[pos + 1..]; 
-                    let value = Value::from_json(&value.to_string()).unwrap_or_else(|_| {
-                        eprintln!("Failed to parse value: {}", key);
-                        exit(1);
-                    });  
-                    context.insert(key.to_string(), value);
-                } else {
-                    eprintln!("Warning: ignoring malformed argument '{}'. Expected key=value.", arg);
-                }
-            } 
+            // Build the context from --data files and key=value pairs.
+            let context = build_context(&args[3..]);
 
-            let template_manager = TemplateManager::new(""); 
-            template_manager.render(file_path, &context) 
+            let (template_manager, errors) = reporting_manager(&template_dir); 
+            if !template_manager.get_template_path(file_path).is_file() {
+                eprintln!("Template not found: {}", template_manager.get_template_path(file_path).display());
+                exit(EXIT_MISSING_TEMPLATE);
+            }
+            let output = template_manager.render(file_path, &context) 
                 .unwrap_or_else(|err| {
                     eprintln!("Failed to render template: {}", err);
-                    exit(1);
-                }) 
+                    exit(EXIT_FAILURE);
+                }); 
+            failed = report_errors(&errors);
+            output
+        }
+        "extract" => {
+            // Every remaining argument is a template file or a directory of templates.
+            let mut keys = BTreeSet::new();
+            for path in args.iter().skip(2) {
+                let mut files = Vec::new();
+                collect_files(Path::new(path), &mut files).unwrap_or_else(|err| {
+                    eprintln!("Failed to read {}: {}", path, err);
+                    exit(EXIT_FAILURE);
+                });
+                for file in files {
+                    // Binary files and other non-text entries are not templates
+                    if let Ok(content) = fs::read_to_string(&file) {
+                        keys.extend(translatable_keys(&tokenize(content)));
+                    }
+                }
+            }
+            lines(keys)
+        }
+        "build" => {
+            // Renders the pages of a site directory and copies everything else.
+            let source = Path::new(&args[2]);
+            let Some(destination) = args.get(3).map(Path::new) else {
+                eprintln!("build expects a source and an output directory");
+                exit(EXIT_USAGE);
+            };
+            let context = build_context(&args[4..]);
+            let built = build_site(source, destination, &context).unwrap_or_else(|err| {
+                eprintln!("Failed to build {}: {}", source.display(), err);
+                exit(EXIT_FAILURE);
+            });
+            if built.failed {
+                print!("{}", lines(built.written));
+                exit(EXIT_FAILURE);
+            }
+            lines(built.written)
+        }
+        "fmt" => {
+            // Reformats templates in place, or with --check only lists those that need it.
+            let mut check = false;
+            let mut options = FormatOptions::default();
+            let mut paths = Vec::new();
+            let mut rest = args.iter().skip(2);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--check" => check = true,
+                    "--indent" => {
+                        let width = rest.next().and_then(|width| width.parse::<usize>().ok()).unwrap_or_else(|| {
+                            eprintln!("--indent expects a number of spaces");
+                            exit(EXIT_USAGE);
+                        });
+                        options.indent = Some(" ".repeat(width));
+                    }
+                    path => paths.push(path),
+                }
+            }
+
+            let mut changed = Vec::new();
+            for path in paths {
+                let mut files = Vec::new();
+                collect_files(Path::new(path), &mut files).unwrap_or_else(|err| {
+                    eprintln!("Failed to read {}: {}", path, err);
+                    exit(EXIT_FAILURE);
+                });
+                for file in files {
+                    // Binary files and other non-text entries are not templates
+                    let Ok(content) = fs::read_to_string(&file) else { continue };
+                    let formatted = format_template(&content, &options).unwrap_or_else(|err| {
+                        eprintln!("Failed to format {}: {}", file.display(), err);
+                        exit(EXIT_FAILURE);
+                    });
+                    if formatted == content {
+                        continue;
+                    }
+                    if !check {
+                        fs::write(&file, formatted).unwrap_or_else(|err| {
+                            eprintln!("Failed to write {}: {}", file.display(), err);
+                            exit(EXIT_FAILURE);
+                        });
+                    }
+                    changed.push(file.display().to_string());
+                }
+            }
+            if check && !changed.is_empty() {
+                eprintln!("Not formatted:\n{}", changed.join("\n"));
+                exit(EXIT_FAILURE);
+            }
+            lines(changed)
         }
         unknown => {
             eprintln!("Unknown command: {}", unknown);
-            exit(1);
+            eprintln!("Run '{} --help' for the commands.", args[0]);
+            exit(EXIT_USAGE);
         } 
     }; 
     
-    println!("Output:\n{}", output); 
-    exit(0)  
+    // The output is written exactly as rendered, so it can be piped or saved as it is.
+    match output_file {
+        Some(path) => fs::write(&path, output).unwrap_or_else(|err| {
+            eprintln!("Failed to write {}: {}", path, err);
+            exit(EXIT_FAILURE);
+        }),
+        None => print!("{}", output),
+    }
+    exit(if failed { EXIT_FAILURE } else { 0 })  
+}
+
+fn print_help(program: &str) {
+    println!("akari {}", env!("CARGO_PKG_VERSION"));
+    println!();
+    println!("Usage: {} [options] <command> <args>...", program);
+    println!();
+    println!("{}", COMMANDS);
+    println!();
+    println!("Options:");
+    println!("  -o, --output <file>     Write the output to a file instead of stdout");
+    println!("  --template-dir <dir>    Directory templates are looked up in (default: current directory)");
+    println!("  -h, --help              Show this help");
+    println!("  -V, --version           Show the version and commands");
+    println!();
+    println!("Exit codes:");
+    println!("  {}  success", 0);
+    println!("  {}  render or I/O error, or unformatted files under fmt --check", EXIT_FAILURE);
+    println!("  {}  usage error", EXIT_USAGE);
+    println!("  {}  template not found", EXIT_MISSING_TEMPLATE);
+}
+
+/// The value following an option, or a usage error if there is none
+fn option_value(option: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| {
+        eprintln!("{} expects a value", option);
+        exit(EXIT_USAGE);
+    })
+}
+
+/// A manager for `template_dir` that collects template errors instead of writing them
+/// into the output
+fn reporting_manager(template_dir: &Path) -> (TemplateManager, Arc<Mutex<Vec<String>>>) {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let collected = errors.clone();
+    let template_manager = TemplateManager::new(template_dir)
+        .with_error_policy(ErrorPolicy::callback(move |message| collected.lock().unwrap().push(message.to_string())));
+    (template_manager, errors)
+}
+
+/// Prints the collected template errors, returning whether there were any
+fn report_errors(errors: &Mutex<Vec<String>>) -> bool {
+    let errors = errors.lock().unwrap();
+    for error in errors.iter() {
+        eprintln!("Template error: {}", error);
+    }
+    !errors.is_empty()
+}
+
+/// One item per line, each ending with a newline
+fn lines<I: IntoIterator<Item = S>, S: AsRef<str>>(items: I) -> String {
+    items.into_iter().map(|item| format!("{}\n", item.as_ref())).collect()
+}
+
+/// Builds the render context from `--data <file>` JSON objects, merged in order so later
+/// files override earlier ones (`-` reads one from stdin), and then `key=value` pairs,
+/// whose values are JSON, on top
+fn build_context(args: &[String]) -> HashMap<String, Value> {
+    let mut context: HashMap<String, Value> = HashMap::new();
+    let mut pairs = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--data" {
+            let source = rest.next().unwrap_or_else(|| {
+                eprintln!("--data expects a JSON file, or - for stdin");
+                exit(EXIT_USAGE);
+            });
+            let json = if source == "-" {
+                io::read_to_string(io::stdin())
+            } else {
+                fs::read_to_string(source)
+            }.unwrap_or_else(|err| {
+                eprintln!("Failed to read data {}: {}", source, err);
+                exit(EXIT_FAILURE);
+            });
+            match Value::from_json(&json) {
+                Ok(Value::Dict(data)) => context.extend(data),
+                Ok(_) => {
+                    eprintln!("Data {} must be a JSON object", source);
+                    exit(EXIT_FAILURE);
+                }
+                Err(err) => {
+                    eprintln!("Failed to parse data {}: {}", source, err);
+                    exit(EXIT_FAILURE);
+                }
+            }
+        } else if let Some((key, value)) = arg.split_once('=') {
+            let value = Value::from_json(value).unwrap_or_else(|_| {
+                eprintln!("Failed to parse value: {}", key);
+                exit(EXIT_USAGE);
+            });
+            pairs.push((key.to_string(), value));
+        } else {
+            eprintln!("Warning: ignoring malformed argument '{}'. Expected key=value.", arg);
+        }
+    }
+    context.extend(pairs);
+    context
+}
+
+/// The files written by `build`, and whether any page had errors
+struct Built {
+    written: Vec<String>,
+    failed: bool,
+}
+
+/// Renders every page under `source` into `destination` and copies the other files.
+///
+/// Files ending in `.html`, `.htm`, `.xhtml` or `.xml` are templates. Those under a path
+/// component starting with `_` (`_layouts/base.html`, `_nav.html`) are partials and neither
+/// rendered nor copied, and so is a template whose front matter sets `"partial": true`.
+/// Front matter is a JSON object between two `---` lines at the top of a template, and its
+/// other keys are added to the context of that page. Hidden files are skipped.
+///
+/// One manager, with caching, renders every page, so shared layouts are read once.
+/// Template errors are printed with the page and make the build fail after every page is written.
+fn build_site(source: &Path, destination: &Path, context: &HashMap<String, Value>) -> Result<Built, String> {
+    let mut files = Vec::new();
+    // Compared as canonical paths, as `out` and `./out` name the same directory.
+    // An output directory that does not exist yet has nothing in it to leave out.
+    let output_directory = fs::canonicalize(destination).ok();
+    site_files(source, source, output_directory.as_deref(), &mut files).map_err(|err| err.to_string())?;
+
+    // Templates are served without their front matter
+    let mut templates = Vec::new();
+    let mut assets = Vec::new();
+    for (path, name) in files {
+        if !is_template(&path) {
+            if !is_partial(&name) {
+                assets.push((path, name));
+            }
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let (front_matter, body) = split_front_matter(&content).map_err(|err| format!("{}: {}", path.display(), err))?;
+        templates.push((name, body.to_string(), front_matter));
+    }
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let collected = errors.clone();
+    let template_manager = TemplateManager::new(source)
+        .with_caching(true)
+        .with_error_policy(ErrorPolicy::callback(move |message| collected.lock().unwrap().push(message.to_string())))
+        .with_embedded_templates(templates.iter().map(|(name, body, _)| (name.clone(), body.clone())).collect::<Vec<_>>());
+
+    let mut built = Built { written: Vec::new(), failed: false };
+    for (name, _, front_matter) in &templates {
+        if is_partial(name) || front_matter.get("partial").boolean() {
+            continue;
+        }
+        let mut page_context = context.clone();
+        if let Value::Dict(page_data) = front_matter {
+            page_context.extend(page_data.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+        let output = template_manager.render(name, &page_context);
+        let page_errors: Vec<String> = errors.lock().unwrap().drain(..).collect();
+        let output = match output {
+            Ok(output) if page_errors.is_empty() => output,
+            Ok(output) => {
+                for error in page_errors {
+                    eprintln!("{}: {}", name, error);
+                }
+                built.failed = true;
+                output
+            }
+            Err(err) => {
+                eprintln!("{}: {}", name, err);
+                built.failed = true;
+                continue;
+            }
+        };
+        let target = destination.join(name);
+        write_file(&target, output.as_bytes())?;
+        built.written.push(target.display().to_string());
+    }
+    for (path, name) in assets {
+        let target = destination.join(&name);
+        let content = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        write_file(&target, &content)?;
+        built.written.push(target.display().to_string());
+    }
+    Ok(built)
+}
+
+/// Lists the files under `directory` with their `/`-separated names relative to `root`,
+/// leaving out hidden entries and the output directory, given as a canonical path
+fn site_files(root: &Path, directory: &Path, destination: Option<&Path>, files: &mut Vec<(PathBuf, String)>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            if destination.is_some_and(|destination| fs::canonicalize(&path).is_ok_and(|path| path == destination)) {
+                continue;
+            }
+            site_files(root, &path, destination, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let name = relative.components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((path, name));
+        }
+    }
+    Ok(())
+}
+
+fn is_template(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|extension| matches!(extension.as_str(), "html" | "htm" | "xhtml" | "xml"))
+}
+
+/// Whether a file is only used by other templates, by a path component starting with `_`
+fn is_partial(name: &str) -> bool {
+    name.split('/').any(|component| component.starts_with('_'))
+}
+
+/// Splits a leading `---` JSON `---` block from a template. Without one, the front matter is None.
+fn split_front_matter(content: &str) -> Result<(Value, &str), String> {
+    let Some(rest) = content.strip_prefix("---\n").or_else(|| content.strip_prefix("---\r\n")) else {
+        return Ok((Value::None, content));
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let front_matter = Value::from_json(&rest[..offset]).map_err(|err| format!("invalid front matter: {}", err))?;
+            if !matches!(front_matter, Value::Dict(_)) {
+                return Err("front matter must be a JSON object".to_string());
+            }
+            return Ok((front_matter, &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    Err("front matter is not closed with ---".to_string())
+}
+
+/// Writes a file, creating its directory first
+fn write_file(path: &Path, content: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| format!("{}: {}", parent.display(), err))?;
+    }
+    fs::write(path, content).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Lists a file, or every file under a directory
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_files(&entry?.path(), files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
 }
- 
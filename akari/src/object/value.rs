@@ -324,7 +324,54 @@ impl Value {
             Ok(json) => json,
             Err(_) => "null".to_string(),
         }
-    } 
+    }
+
+    /// Converts the Value into indented JSON, two spaces per level, for reading rather than
+    /// parsing. The keys of dictionaries are sorted so the output is the same every time.
+    /// # Example
+    /// ```rust
+    /// use akari::Value;
+    /// let obj = Value::from_json(r#"{"b": [1, 2], "a": "x", "c": {}}"#).unwrap();
+    /// assert_eq!(obj.into_json_pretty(), "{\n  \"a\": \"x\",\n  \"b\": [\n    1,\n    2\n  ],\n  \"c\": {}\n}");
+    /// ```
+    pub fn into_json_pretty(&self) -> String {
+        let mut json = String::new();
+        self.write_json_pretty(&mut json, 0);
+        json
+    }
+
+    fn write_json_pretty(&self, json: &mut String, depth: usize) {
+        let indent = "  ";
+        match self {
+            Value::List(list) if !list.is_empty() => {
+                json.push('[');
+                for (i, item) in list.iter().enumerate() {
+                    json.push_str(if i == 0 { "\n" } else { ",\n" });
+                    json.push_str(&indent.repeat(depth + 1));
+                    item.write_json_pretty(json, depth + 1);
+                }
+                json.push('\n');
+                json.push_str(&indent.repeat(depth));
+                json.push(']');
+            }
+            Value::Dict(dict) if !dict.is_empty() => {
+                let mut entries: Vec<(&String, &Value)> = dict.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                json.push('{');
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    json.push_str(if i == 0 { "\n" } else { ",\n" });
+                    json.push_str(&indent.repeat(depth + 1));
+                    json.push_str(&Value::Str(key.clone()).into_json());
+                    json.push_str(": ");
+                    value.write_json_pretty(json, depth + 1);
+                }
+                json.push('\n');
+                json.push_str(&indent.repeat(depth));
+                json.push('}');
+            }
+            _ => json.push_str(&self.into_json()),
+        }
+    }
 
     /// Converts the Value into a JSON string representation and writes it to a file.
     /// This function will return an error if the file cannot be written.
@@ -5,6 +5,15 @@ pub mod parse;
 pub mod compile;
 pub mod template_manager; 
 pub mod templates;  
+pub mod analysis; 
+pub mod i18n; 
+pub mod escape; 
+pub mod context; 
+pub mod minify; 
+pub mod cache; 
+pub mod source_map; 
+pub mod output; 
+pub mod format; 
 
 #[cfg(test)] 
 mod test; 
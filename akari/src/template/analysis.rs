@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Value as Obj;
+use super::parse::{Token, StringPart};
+
+/// Static information about a template and everything it pulls in,
+/// as returned by [`TemplateManager::analyze`](crate::TemplateManager::analyze).
+///
+/// Template names are resolved paths relative to the template directory,
+/// the same names `TemplateManager` caches tokens under.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateAnalysis {
+    /// The analyzed template
+    pub name: String,
+    /// Ancestors named by `template`, nearest parent first
+    pub parent_chain: Vec<String>,
+    /// Every template pulled in with `insert`, directly or through a parent or another partial
+    pub inserts: BTreeSet<String>,
+    /// Block names mapped to the templates of the chain defining them, root ancestor first.
+    /// The last template in each list provides the rendered content.
+    pub blocks: BTreeMap<String, Vec<String>>,
+    /// Blocks defined by more than one template of the chain
+    pub overridden_blocks: BTreeSet<String>,
+    /// Blocks defined in the chain that no ancestor has a slot for, so they never render
+    pub dead_blocks: BTreeSet<String>,
+    /// `placeholder` names with no block providing them
+    pub unresolved_placeholders: BTreeSet<String>,
+    /// The variables each template reads before binding them itself, for every template
+    /// visited: the chain, the partials and their own parents
+    pub free_variables: BTreeMap<String, BTreeSet<String>>,
+    /// The variables the fully expanded template reads before binding them, i.e. what the caller
+    /// must provide. See [`free_variables`] for how bindings are tracked.
+    pub required_variables: BTreeSet<String>,
+    /// Parents and partials that could not be loaded
+    pub missing_templates: BTreeSet<String>,
+    /// Data files read by `load` in any template visited
+    pub data_files: BTreeSet<String>,
+}
+
+impl TemplateAnalysis {
+    /// All templates and data files whose change affects the rendered output, the template
+    /// itself included. Missing templates are listed too, since creating one changes the output.
+    pub fn dependencies(&self) -> BTreeSet<String> {
+        let mut dependencies: BTreeSet<String> = self.free_variables.keys().cloned().collect();
+        dependencies.extend(self.missing_templates.iter().cloned());
+        dependencies.extend(self.data_files.iter().cloned());
+        dependencies
+    }
+}
+
+/// Names following `template`, `insert` or `load` directives, in order of appearance
+pub fn referenced_templates(tokens: &[Token], keyword: &Token) -> Vec<String> {
+    tokens.windows(2)
+        .filter(|pair| &pair[0] == keyword)
+        .filter_map(|pair| match &pair[1] {
+            Token::Object(Obj::Str(name)) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Names following `block` or `placeholder` directives
+pub fn named_sections(tokens: &[Token], keyword: &Token) -> BTreeSet<String> {
+    tokens.windows(2)
+        .filter(|pair| &pair[0] == keyword)
+        .filter_map(|pair| match &pair[1] {
+            Token::Identifier(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the variables a token stream reads before binding them with `let`, `for` or `load`.
+///
+/// Reads are checked in template order, so `-[ x ]--[ let x = 1 ]-` still needs `x`, and a
+/// binding takes effect at the end of its statement, so `let x = x + 1` reads `x`. Variables
+/// set by a directive stay set after it, even inside an `if` or a loop body, so a binding in
+/// a branch that may not run still counts for what follows.
+///
+/// Property names after `.`/`?.`, block and placeholder names, the targets of `del`,
+/// function names, named call arguments, the `step` clause of ranges and the `ttl` clause
+/// of `cache` are not variable reads, and `load ... as name` binds `name`.
+/// Expressions inside interpolated strings are included.
+pub fn free_variables(tokens: &[Token]) -> BTreeSet<String> {
+    let mut read = BTreeSet::new();
+    let mut bound = BTreeSet::new();
+    collect_variables(tokens, &mut read, &mut bound);
+    read
+}
+
+fn collect_variables(tokens: &[Token], read: &mut BTreeSet<String>, bound: &mut BTreeSet<String>) {
+    // Names bound by the current statement, which only apply once it ends
+    let mut binding = Vec::new();
+    let mut in_range = false;
+    let mut in_cache = false;
+    let mut in_load = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = if i > 0 { tokens.get(i - 1) } else { None };
+        match token {
+            Token::Identifier(name) => {
+                if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                    continue; // Stray punctuation is lexed as an identifier
+                }
+                let next = tokens.get(i + 1);
+                match previous {
+                    // Function names and `name = value` call arguments
+                    _ if next == Some(&Token::LeftParen) => {},
+                    Some(Token::Comma) if next == Some(&Token::Assignment) => {},
+                    Some(Token::LetKeyword) | Some(Token::ForKeyword) => {
+                        binding.push(name.clone());
+                    },
+                    Some(Token::Dot) | Some(Token::OptionalDot) | Some(Token::BlockKeyword)
+                    | Some(Token::PlaceholderKeyword) | Some(Token::DelKeyword) => {},
+                    _ if in_range && name == "step" => {},
+                    _ if in_cache && name == "ttl" => {},
+                    Some(Token::Identifier(word)) if in_load && word == "as" => {
+                        binding.push(name.clone());
+                    },
+                    _ if in_load && name == "as" => {},
+                    _ if bound.contains(name) => {},
+                    _ => {
+                        read.insert(name.clone());
+                    }
+                }
+            },
+            Token::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expression(expression) = part {
+                        collect_variables(expression, read, bound);
+                    }
+                }
+            },
+            Token::Range | Token::RangeInclusive => in_range = true,
+            Token::CacheKeyword => in_cache = true,
+            Token::LoadKeyword => in_load = true,
+            Token::EndOfStatement => {
+                bound.extend(binding.drain(..));
+                in_range = false;
+                in_cache = false;
+                in_load = false;
+            },
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A store for the rendered output of `cache` directives.
+///
+/// Implement this to keep fragments somewhere shared, such as Redis, so they survive
+/// restarts or are shared between processes. [`LruCache`] keeps them in memory.
+pub trait FragmentCache: Send + Sync {
+    /// The fragment stored under `key`, unless it is missing or expired
+    fn get(&self, key: &str) -> Option<String>;
+
+    /// Stores a fragment, for `ttl` if given and otherwise until it is evicted
+    fn set(&self, key: &str, fragment: String, ttl: Option<Duration>);
+
+    /// Removes a fragment, e.g. after the data it was rendered from changed
+    fn remove(&self, key: &str);
+
+    /// Removes every fragment
+    fn clear(&self);
+}
+
+/// An in-memory [`FragmentCache`] holding a fixed number of fragments.
+/// When it is full, the least recently used fragment makes room for a new one.
+#[derive(Debug)]
+pub struct LruCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    entries: HashMap<String, Entry>,
+    /// Incremented on every access, so the entry with the lowest stamp is the least recently used
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    fragment: String,
+    expires: Option<Instant>,
+    last_used: u64,
+}
+
+impl LruCache {
+    /// Creates a cache holding at most `capacity` fragments
+    pub fn new(capacity: usize) -> Self {
+        LruCache { capacity, state: Mutex::new(LruState::default()) }
+    }
+
+    /// The number of fragments stored, expired ones included until they are looked up or evicted
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Whether no fragments are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for LruCache {
+    fn default() -> Self {
+        LruCache::new(1024)
+    }
+}
+
+impl FragmentCache for LruCache {
+    fn get(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        if entry.expires.is_some_and(|expires| expires <= Instant::now()) {
+            state.entries.remove(key);
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.fragment.clone())
+    }
+
+    fn set(&self, key: &str, fragment: String, ttl: Option<Duration>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        if !state.entries.contains_key(key) && state.entries.len() >= self.capacity {
+            let now = Instant::now();
+            // Expired fragments go first, then the least recently used one
+            let victim = state.entries.iter()
+                .min_by_key(|(_, entry)| (entry.expires.is_none_or(|expires| expires > now), entry.last_used))
+                .map(|(key, _)| key.clone());
+            if let Some(victim) = victim {
+                state.entries.remove(&victim);
+            }
+        }
+        let entry = Entry { fragment, expires: ttl.map(|ttl| Instant::now() + ttl), last_used: state.clock };
+        state.entries.insert(key.to_string(), entry);
+    }
+
+    fn remove(&self, key: &str) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+}
@@ -1,12 +1,142 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 use crate::Value as Obj; 
-use super::parse::Token; 
+use super::parse::{Token, StringPart}; 
+use super::i18n::{Catalog, format_message}; 
+use super::escape::{self, HtmlContext, ESCAPE_FUNCTIONS}; 
+use super::context::Context; 
+use super::minify::Minifier; 
+use super::cache::FragmentCache; 
+use super::source_map::{SourceLocation, SourceMap}; 
+use super::output::OutputMode; 
 
-pub fn compile(tokens: Vec<Token>, mut data: HashMap<String, Obj>) -> Result<String, String> {
-    let mut compiler = TemplateCompiler::new(tokens, data);
-    compiler.compile()
+pub fn compile(tokens: Vec<Token>, data: HashMap<String, Obj>) -> Result<String, String> {
+    compile_with(tokens, data, &RenderOptions::default()).map(|compiled| compiled.output)
 } 
 
+/// Compiles the tokens with the given render options and returns everything the render
+/// produced, including the variables as the template left them.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use akari::{compile_with, tokenize, RenderOptions, Value};
+///
+/// let mut data = HashMap::new();
+/// data.insert("name".to_string(), Value::new("Ann"));
+/// let options = RenderOptions { run_exports: true, ..RenderOptions::default() };
+/// let compiled = compile_with(tokenize("-[ let greeting = \"Hi ${name}\" ]--[ greeting ]-"), data, &options).unwrap();
+/// assert_eq!(compiled.output, "Hi Ann");
+/// assert_eq!(compiled.context.get("greeting"), Some(&Value::new("Hi Ann")));
+/// ```
+pub fn compile_with<C: Into<Context>>(tokens: Vec<Token>, context: C, options: &RenderOptions) -> Result<Compiled, String> {
+    let mut compiler = TemplateCompiler::new(tokens, context.into(), options.clone());
+    let output = compiler.compile()?;
+    Ok(Compiled { output, context: compiler.data, source_map: compiler.source_map })
+} 
+
+/// Everything a render produces, as returned by [`compile_with`]
+#[derive(Debug, Clone, Default)]
+pub struct Compiled {
+    /// The rendered text
+    pub output: String,
+    /// The variables as the template left them. Lazy values it never read are still unevaluated.
+    pub context: Context,
+    /// Where each part of the output came from, if [`RenderOptions::source_map`] was set
+    pub source_map: SourceMap,
+}
+
+/// Settings that apply to a whole render, shared by every part of the template
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+    /// How recoverable template errors are reported
+    pub error_policy: ErrorPolicy,
+    /// Messages for `trans` and `_(...)`. Without one, the key itself is the message.
+    pub catalog: Option<Arc<dyn Catalog>>,
+    /// Escape every value written by `output`, a bare variable or `trans` for where it lands
+    /// in the HTML: text, attribute, URL, script or style. Single calls to an escape function
+    /// or `raw(...)` are written as they are.
+    pub auto_escape: bool,
+    /// Minify the HTML as it is written, see [`Minifier`](super::minify::Minifier)
+    pub minify: bool,
+    /// Store for the output of `cache` blocks. Without one, they render every time.
+    /// Keys are shared by every template using the store, so they should say what they cache.
+    pub fragment_cache: Option<Arc<dyn FragmentCache>>,
+    /// The kind of document rendered. Escaping and inline errors follow it, and only
+    /// `Html` output is minified.
+    pub output_mode: OutputMode,
+    /// Write the values of `debug` directives. Without it, they write nothing.
+    pub debug: bool,
+    /// Run templates marked `export`, which render nothing, for the variables they set
+    pub run_exports: bool,
+    /// Record which template, line and column wrote each part of the output. Only tokens
+    /// from [`tokenize_with_locations`](super::parse::tokenize_with_locations) carry locations.
+    pub source_map: bool,
+}
+
+impl fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderOptions")
+            .field("error_policy", &self.error_policy)
+            .field("catalog", &self.catalog.as_ref().map(|_| ".."))
+            .field("auto_escape", &self.auto_escape)
+            .field("minify", &self.minify)
+            .field("fragment_cache", &self.fragment_cache.as_ref().map(|_| ".."))
+            .field("output_mode", &self.output_mode)
+            .field("debug", &self.debug)
+            .field("run_exports", &self.run_exports)
+            .field("source_map", &self.source_map)
+            .finish()
+    }
+}
+
+/// What to do with recoverable template errors, such as an undefined variable,
+/// a missing block or a missing parent or inserted template.
+#[derive(Clone, Default)]
+pub enum ErrorPolicy {
+    /// Render the error as an HTML comment in place and keep going
+    #[default]
+    Inline,
+    /// Abort the render and return the error
+    Fail,
+    /// Pass the error message to the callback, render nothing in its place and keep going
+    Callback(Arc<dyn Fn(&str) + Send + Sync>),
+}
+
+impl ErrorPolicy {
+    /// Creates a `Callback` policy from a closure
+    pub fn callback<F: Fn(&str) + Send + Sync + 'static>(callback: F) -> Self {
+        ErrorPolicy::Callback(Arc::new(callback))
+    }
+
+    /// Applies the policy to an error, rendering it inline the way `mode` renders errors.
+    /// Returns the text to render in its place, or the error itself if the render should fail.
+    pub fn handle_in(&self, message: String, mode: &OutputMode) -> Result<String, String> {
+        match self {
+            ErrorPolicy::Inline => Ok(mode.format_error(&message)),
+            ErrorPolicy::Fail => Err(message),
+            ErrorPolicy::Callback(callback) => {
+                callback(&message);
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorPolicy::Inline => write!(f, "Inline"),
+            ErrorPolicy::Fail => write!(f, "Fail"),
+            ErrorPolicy::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+/// Positional and `name = value` arguments of a call
+type Arguments = (Vec<Obj>, Vec<(String, Obj)>);
+
 // AccessType enum to distinguish between reading and writing operations
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum AccessType {
@@ -16,16 +146,27 @@ enum AccessType {
 
 struct TemplateCompiler {
     tokens: Vec<Token>,
-    data: HashMap<String, Obj>,
+    data: Context,
     pos: usize,
     blocks: HashMap<String, Vec<Token>>,
     output: String,
     export_mode: bool,
     template_name: Option<String>,
+    options: RenderOptions,
+    html_context: HtmlContext,
+    minifier: Minifier,
+    /// Where the text or directive being compiled comes from, if the tokens carry locations
+    location: Option<SourceLocation>,
+    source_map: SourceMap,
+    /// Errors reported so far, including those of nested parts
+    errors: usize,
 }
 
 impl TemplateCompiler {
-    fn new(tokens: Vec<Token>, data: HashMap<String, Obj>) -> Self {
+    fn new(tokens: Vec<Token>, data: Context, mut options: RenderOptions) -> Self {
+        if !options.output_mode.is_html() {
+            options.minify = false;
+        }
         TemplateCompiler {
             tokens,
             data,
@@ -34,10 +175,103 @@ impl TemplateCompiler {
             output: String::new(),
             export_mode: false,
             template_name: None,
+            options,
+            html_context: HtmlContext::default(),
+            minifier: Minifier::default(),
+            location: None,
+            source_map: SourceMap::default(),
+            errors: 0,
         }
     }
+    
+    /// A compiler for a nested part of the template, starting from this one's data and HTML context
+    fn sub_compiler(&self, tokens: Vec<Token>) -> TemplateCompiler {
+        let mut compiler = TemplateCompiler::new(tokens, self.data.clone(), self.options.clone());
+        compiler.html_context = self.html_context.clone();
+        compiler.minifier = self.minifier.clone();
+        compiler.location = self.location.clone();
+        compiler
+    }
+    
+    /// Appends the output of a sub-compiler and carries on from where it left the data and HTML context
+    fn absorb(&mut self, compiler: TemplateCompiler, output: &str) {
+        self.source_map.extend_at(compiler.source_map, self.output.len());
+        self.output.push_str(output);
+        self.data = compiler.data;
+        self.html_context = compiler.html_context;
+        self.minifier = compiler.minifier;
+        self.location = compiler.location;
+        self.errors += compiler.errors;
+    }
+    
+    /// Writes template text to the output
+    fn write_html(&mut self, html: &str) {
+        if self.options.auto_escape && self.options.output_mode.is_html() {
+            self.html_context.feed(html);
+        }
+        let start = self.output.len();
+        if self.options.minify {
+            self.minifier.write(html, &mut self.output);
+        } else {
+            self.output.push_str(html);
+        }
+        if let Some(location) = &self.location
+            && self.options.source_map {
+            self.source_map.push(start..self.output.len(), location);
+        }
+    }
+    
+    /// Writes a value to the output, escaping it for the output mode when auto-escaping
+    /// is on and the value was not escaped explicitly. HTML is escaped for the context
+    /// the value lands in, text is never escaped.
+    fn write_value(&mut self, value: &Obj, explicitly_escaped: bool) {
+        let text = value.interal_value_as_string();
+        if !self.options.auto_escape || explicitly_escaped {
+            self.write_html(&text);
+            return;
+        }
+        let escaped = match &self.options.output_mode {
+            OutputMode::Html => self.html_context.current().escape(&text),
+            OutputMode::Text => text,
+            OutputMode::Custom(format) => format.escape(&text),
+        };
+        self.write_html(&escaped);
+    }
+    
+    /// Whether the expression at the current position is a single call to an escape function or `raw`
+    fn is_explicitly_escaped(&mut self) -> bool {
+        let start = self.pos;
+        let explicit = matches!(self.tokens.get(self.pos), Some(Token::Identifier(name)) if ESCAPE_FUNCTIONS.contains(&name.as_str()))
+            && matches!(self.tokens.get(self.pos + 1), Some(Token::LeftParen))
+            && self.skip_operand().is_ok()
+            && matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement) | None);
+        self.pos = start;
+        explicit
+    }
+    
+    /// Whether the operand at the current position is followed by `??`, so the directive is
+    /// a fallback expression rather than a plain variable read
+    fn is_null_coalescing(&mut self) -> bool {
+        let start = self.pos;
+        let coalescing = self.skip_operand().is_ok()
+            && matches!(self.tokens.get(self.pos), Some(Token::NullCoalesce));
+        self.pos = start;
+        coalescing
+    }
+    
+    /// Reports a recoverable error through the error policy
+    fn report_error(&mut self, message: String) -> Result<(), String> {
+        self.errors += 1;
+        let rendered = self.options.error_policy.handle_in(message, &self.options.output_mode)?;
+        self.write_html(&rendered);
+        Ok(())
+    }
 
     fn compile(&mut self) -> Result<String, String> {
+        self.run(self.options.run_exports)
+    }
+    
+    fn run(&mut self, run_exports: bool) -> Result<String, String> {
         // First pass: identify blocks and template info
         self.collect_blocks_and_metadata()?; 
         
@@ -47,11 +281,18 @@ impl TemplateCompiler {
         
         // If this is a template file with export directive, we don't directly output
         if self.export_mode {
+            if run_exports {
+                self.generate_output()?;
+            }
             return Ok(String::new());
         }
         
         // Second pass: generate output
-        self.generate_output()
+        self.generate_output()?;
+        if self.options.minify {
+            self.minifier.finish(&mut self.output);
+        }
+        Ok(std::mem::take(&mut self.output))
     }
     
     fn collect_blocks_and_metadata(&mut self) -> Result<(), String> {
@@ -119,7 +360,8 @@ impl TemplateCompiler {
         while self.pos < self.tokens.len() {
             match &self.tokens[self.pos] {
                 Token::HtmlContent(content) => {
-                    self.output.push_str(content);
+                    let content = content.clone();
+                    self.write_html(&content);
                     self.pos += 1;
                 },
                 Token::PlaceholderKeyword => {
@@ -128,21 +370,16 @@ impl TemplateCompiler {
                         let block_name = name.clone();
                         if let Some(block_tokens) = self.blocks.get(&block_name) {
                             // Create a new compiler to process this block
-                            let mut block_compiler = TemplateCompiler::new(
-                                block_tokens.clone(), 
-                                self.data.clone()
-                            );
+                            let mut block_compiler = self.sub_compiler(block_tokens.clone());
                             match block_compiler.generate_output() {
                                 Ok(block_output) => {
-                                    self.output.push_str(&block_output);
-                                    // Update data with any changes from the block
-                                    self.data = block_compiler.data;
+                                    self.absorb(block_compiler, &block_output);
                                 },
                                 Err(e) => return Err(e),
                             }
                         } else {
                             // Use empty content for missing blocks
-                            self.output.push_str(&format!("<!-- Block '{}' not defined -->", block_name));
+                            self.report_error(format!("Block '{}' not defined", block_name))?;
                         }
                         self.pos += 1;
                     } else {
@@ -192,24 +429,63 @@ impl TemplateCompiler {
                 },
                 Token::OutputKeyword => {
                     self.pos += 1;
+                    let explicitly_escaped = self.is_explicitly_escaped();
                     let value = self.evaluate_expression()?;
-                    self.output.push_str(&value.interal_value_as_string());
+                    self.write_value(&value, explicitly_escaped);
                 },
                 Token::DelKeyword => {
                     self.handle_deletion()?;
                 },
+                Token::TransKeyword => {
+                    self.handle_trans()?;
+                },
+                Token::CacheKeyword => {
+                    self.handle_cache()?;
+                },
+                Token::AssertKeyword => {
+                    self.handle_assert()?;
+                },
+                Token::DebugKeyword => {
+                    self.handle_debug()?;
+                },
+                Token::LoadKeyword => {
+                    self.handle_unresolved_load()?;
+                },
                 Token::Identifier(name) => {
                     let var_name = name.clone();
+                    if self.is_null_coalescing() {
+                        // `-[ x ?? "default" ]-` writes the whole expression
+                        match self.evaluate_expression() {
+                            Ok(value) => self.write_value(&value, false),
+                            Err(e) => {
+                                while !matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement) | None) {
+                                    self.pos += 1;
+                                }
+                                self.report_error(e)?;
+                            }
+                        }
+                        continue;
+                    }
+                    let explicitly_escaped = self.is_explicitly_escaped();
                     self.pos += 1;
                     
                     // Check for access operations (index, property, or assignment)
                     match self.handle_variable_access(&var_name, true)? {
                         Some(value) => {
-                            self.output.push_str(&value.interal_value_as_string());
+                            self.write_value(&value, explicitly_escaped);
                         },
                         None => {} // Assignment was handled in handle_variable_access
                     }
                 },
+                Token::Location(location) => {
+                    self.location = Some(location.clone());
+                    self.pos += 1;
+                },
+                Token::Error(message) => {
+                    let message = message.clone();
+                    self.pos += 1;
+                    self.report_error(message)?;
+                },
                 Token::EndOfStatement => {
                     self.pos += 1;
                 },
@@ -226,182 +502,67 @@ impl TemplateCompiler {
     /// Handles variable access, including indexing, property access, and assignments
     /// Returns Some(value) for read operations, None for write operations
     fn handle_variable_access(&mut self, var_name: &str, output_to_stream: bool) -> Result<Option<Obj>, String> {
-        // Check if this is a property access or indexing
-        let mut value = match self.data.get(var_name) {
+        if matches!(self.tokens.get(self.pos), Some(Token::LeftParen)) {
+            let value = match self.handle_function_call(var_name) {
+                Ok(value) => self.parse_access_chain(value),
+                Err(e) => Err(e),
+            };
+            return match value {
+                Ok(value) => Ok(Some(value)),
+                Err(e) if output_to_stream => {
+                    // Drop the rest of the directive so the arguments are not misread as statements
+                    while !matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement) | None) {
+                        self.pos += 1;
+                    }
+                    self.report_error(e)?;
+                    Ok(Some(Obj::None))
+                },
+                Err(e) => Err(e),
+            };
+        }
+        
+        // A plain assignment replaces the value without reading it, so a lazy value is not computed
+        if matches!(self.tokens.get(self.pos), Some(Token::Assignment)) {
+            self.pos += 1;
+            let new_value = self.evaluate_expression()?;
+            self.data.insert(var_name, new_value);
+            return Ok(None);
+        }
+        
+        let value = match self.data.get(var_name) {
             Some(v) => v.clone(),
             None => {
+                // An undefined variable reads as None through its whole access chain
+                self.skip_access_chain()?;
                 if output_to_stream {
-                    self.output.push_str(
-                        &format!("<!-- There should be a value '{}' but not found -->", var_name)
-                    );
+                    // Nothing is written in its place besides the error
+                    self.report_error(format!("There should be a value '{}' but not found", var_name))?;
+                    return Ok(None);
                 }
                 return Ok(Some(Obj::None));
             }
         };
         
-        let mut access_chain_pos = self.pos;
-        
-        // Handle indexing with [] syntax
-        while matches!(self.tokens.get(access_chain_pos), Some(Token::LeftSquareBracket)) {
-            access_chain_pos += 1;
-            self.pos = access_chain_pos;
-            
-            // Evaluate the index expression
-            let index = self.evaluate_expression()?;
-            access_chain_pos = self.pos;
-            
-            if !matches!(self.tokens.get(access_chain_pos), Some(Token::RightSquareBracket)) {
-                return Err("Expected closing bracket after index".to_string());
-            }
-            access_chain_pos += 1;
-            
-            // Apply the indexing to get the new value
-            match &value {
-                Obj::List(list) => {
-                    match &index {
-                        Obj::Numerical(n) => {
-                            let idx = *n as usize;
-                            if idx < list.len() {
-                                value = list[idx].clone();
-                            } else if output_to_stream {
-                                self.output.push_str(
-                                    &format!("<!-- Index {} out of bounds for list -->", idx)
-                                );
-                                return Ok(Some(Obj::None));
-                            } else {
-                                return Err(format!("Index {} out of bounds for list", idx));
-                            }
-                        },
-                        _ => {
-                            if output_to_stream {
-                                self.output.push_str("<!-- List index must be a number -->");
-                                return Ok(Some(Obj::None));
-                            } else {
-                                return Err("List index must be a number".to_string());
-                            }
-                        }
-                    }
-                },
-                Obj::Dict(dict) => {
-                    // Allow both string literals and string expressions as keys
-                    let key = index.interal_value_as_string();
-                    if let Some(val) = dict.get(&key) {
-                        value = val.clone();
-                    } else if output_to_stream {
-                        self.output.push_str(
-                            &format!("<!-- Key '{}' not found in dictionary -->", key)
-                        );
-                        return Ok(Some(Obj::None));
-                    } else {
-                        return Err(format!("Key '{}' not found in dictionary", key));
-                    }
-                },
-                _ => {
-                    if output_to_stream {
-                        self.output.push_str(
-                            &format!("<!-- Cannot index into a {} value -->", value.type_of())
-                        );
-                        return Ok(Some(Obj::None));
-                    } else {
-                        return Err(format!("Cannot index into a {} value", value.type_of()));
-                    }
-                }
-            }
-        }
-        
-        // Handle dot notation for property/method access
-        if matches!(self.tokens.get(access_chain_pos), Some(Token::Dot)) {
-            access_chain_pos += 1;
-            
-            if let Some(Token::Identifier(prop_name)) = self.tokens.get(access_chain_pos).cloned() {
-                access_chain_pos += 1;
-                
-                // Handle built-in methods and properties
-                match &value {
-                    Obj::List(list) => {
-                        match prop_name.as_str() {
-                            "len" => value = Obj::Numerical(list.len() as f64),
-                            // Add more list methods here as needed
-                            _ => {
-                                if output_to_stream {
-                                    self.output.push_str(
-                                        &format!("<!-- No property/method '{}' on list -->", prop_name)
-                                    );
-                                    return Ok(Some(Obj::None));
-                                } else {
-                                    return Err(format!("No property/method '{}' on list", prop_name));
-                                }
-                            }
-                        }
-                    },
-                    Obj::Dict(dict) => {
-                        match prop_name.as_str() {
-                            "len" => value = Obj::Numerical(dict.len() as f64),
-                            // Add more dictionary methods here as needed
-                            _ => {
-                                // For dictionaries, treat dot notation as an alternative to [] indexing
-                                if let Some(val) = dict.get(&prop_name) {
-                                    value = val.clone();
-                                } else if output_to_stream {
-                                    self.output.push_str(
-                                        &format!("<!-- Key '{}' not found in dictionary -->", prop_name)
-                                    );
-                                    return Ok(Some(Obj::None));
-                                } else {
-                                    return Err(format!("Key '{}' not found in dictionary", prop_name));
-                                }
-                            }
-                        }
-                    },
-                    Obj::Str(s) => {
-                        match prop_name.as_str() {
-                            "len" => value = Obj::Numerical(s.len() as f64),
-                            // Add more string methods here as needed
-                            _ => {
-                                if output_to_stream {
-                                    self.output.push_str(
-                                        &format!("<!-- No property/method '{}' on string -->", prop_name)
-                                    );
-                                    return Ok(Some(Obj::None));
-                                } else {
-                                    return Err(format!("No property/method '{}' on string", prop_name));
-                                }
-                            }
-                        }
-                    },
-                    _ => {
-                        if output_to_stream {
-                            self.output.push_str(
-                                &format!("<!-- Type '{}' does not support properties/methods -->", value.type_of())
-                            );
-                            return Ok(Some(Obj::None));
-                        } else {
-                            return Err(format!("Type '{}' does not support properties/methods", value.type_of()));
-                        }
-                    }
+        // Handle indexing with [] syntax and dot notation for property/method access
+        let access_chain_pos = self.pos;
+        let value = match self.parse_access_chain(value) {
+            Ok(value) => value,
+            Err(e) => {
+                // Step over the rest of the chain so the tokens are not misread afterwards
+                self.pos = access_chain_pos;
+                self.skip_access_chain()?;
+                if output_to_stream {
+                    self.report_error(e)?;
+                    return Ok(Some(Obj::None));
                 }
+                return Err(e);
             }
-        }
-        
-        // Update position after all access operations
-        self.pos = access_chain_pos;
+        };
         
-        // Check if this is an assignment
+        // Assignment to indexed/property value - this is complex and requires maintaining the path
+        // For now, we're not implementing this part fully
         if matches!(self.tokens.get(self.pos), Some(Token::Assignment)) {
-            self.pos += 1;
-            let new_value = self.evaluate_expression()?;
-            
-            // Handle the assignment based on the access chain
-            if self.pos == self.tokens.len() || !matches!(self.tokens.get(access_chain_pos-1), Some(Token::RightSquareBracket) | Some(Token::Dot)) {
-                // Simple variable assignment
-                self.data.insert(var_name.to_string(), new_value);
-            } else {
-                // Assignment to indexed/property value - this is complex and requires maintaining the path
-                // For now, we're not implementing this part fully
-                return Err("Assignment to indexed/property values not fully implemented".to_string());
-            }
-            
-            return Ok(None); // No value to output for assignments
+            return Err("Assignment to indexed/property values not fully implemented".to_string());
         }
         
         Ok(Some(value))
@@ -457,12 +618,10 @@ impl TemplateCompiler {
                             if idx < list.len() {
                                 list.remove(idx);
                             } else {
-                                self.output.push_str(
-                                    &format!("<!-- Index {} out of bounds for list {} -->", idx, var_name)
-                                );
+                                self.report_error(format!("Index {} out of bounds for list {}", idx, var_name))?;
                             }
                         } else {
-                            self.output.push_str("<!-- List index must be a number -->");
+                            self.report_error("List index must be a number".to_string())?;
                         }
                     },
                     Obj::Dict(dict) => {
@@ -470,15 +629,12 @@ impl TemplateCompiler {
                         dict.remove(&key);
                     },
                     _ => {
-                        self.output.push_str(
-                            &format!("<!-- Cannot delete from a {} value -->", collection.type_of())
-                        );
+                        let message = format!("Cannot delete from a {} value", collection.type_of());
+                        self.report_error(message)?;
                     }
                 }
             } else {
-                self.output.push_str(
-                    &format!("<!-- Variable '{}' not found -->", var_name)
-                );
+                self.report_error(format!("Variable '{}' not found", var_name))?;
             }
             
             Ok(())
@@ -487,6 +643,235 @@ impl TemplateCompiler {
         }
     }
     
+    /// Handles `trans "key", name = value, ...`, writing the translated message
+    fn handle_trans(&mut self) -> Result<(), String> {
+        self.pos += 1; // Skip trans
+        let (positional, named) = self.parse_arguments(&Token::EndOfStatement)?;
+        let message = self.translate(positional, named)?;
+        self.write_value(&message, false);
+        Ok(())
+    }
+    
+    /// Handles `assert condition, "message"`. A false condition is reported through the
+    /// error policy like any other template error, so it fails the render under
+    /// `ErrorPolicy::Fail` and reaches the callback under `ErrorPolicy::Callback`.
+    fn handle_assert(&mut self) -> Result<(), String> {
+        self.pos += 1; // Skip assert
+        let holds = self.evaluate_condition()?;
+        let message = if matches!(self.tokens.get(self.pos), Some(Token::Comma)) {
+            self.pos += 1;
+            Some(self.evaluate_expression()?.interal_value_as_string())
+        } else {
+            None
+        };
+        if !holds {
+            match message {
+                Some(message) => self.report_error(format!("Assertion failed: {}", message))?,
+                None => self.report_error("Assertion failed".to_string())?,
+            }
+        }
+        Ok(())
+    }
+    
+    /// Handles `debug expression`. In debug mode the value is written as indented JSON,
+    /// inside a `<pre>` element in HTML output. Otherwise the expression is not even evaluated.
+    fn handle_debug(&mut self) -> Result<(), String> {
+        self.pos += 1; // Skip debug
+        if !self.options.debug {
+            while !matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement) | None) {
+                self.pos += 1;
+            }
+            return Ok(());
+        }
+        let json = self.evaluate_expression()?.into_json_pretty();
+        match &self.options.output_mode {
+            OutputMode::Html => self.write_html(&format!("<pre class=\"akari-debug\">{}</pre>", escape::escape_html(&json))),
+            _ => self.write_html(&json),
+        }
+        Ok(())
+    }
+    
+    /// Reports a `load` directive left in the tokens. `TemplateManager` resolves them into
+    /// `let` directives before compiling, since only it knows where data files are.
+    fn handle_unresolved_load(&mut self) -> Result<(), String> {
+        while !matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement) | None) {
+            self.pos += 1;
+        }
+        self.report_error("`load` directives need a TemplateManager to find their data".to_string())
+    }
+    
+    /// Handles `cache key ttl seconds ... endcache`. The first render stores the output of the
+    /// body under the key, for `ttl` seconds if given, and later renders write the stored
+    /// fragment without running the body. Variables set inside the body stay local to it,
+    /// so a render behaves the same whether the fragment was cached or not.
+    /// With a `locale` variable the fragment is stored under the locale, a NUL character and
+    /// the key, and a body that reported an error is rendered but not stored.
+    fn handle_cache(&mut self) -> Result<(), String> {
+        let start = self.pos;
+        self.pos += 1; // Skip cache
+        
+        // Find the body between the end of this directive and the matching endcache
+        let body_start = self.tokens[self.pos..].iter()
+            .position(|token| matches!(token, Token::EndOfStatement))
+            .map_or(self.tokens.len(), |offset| self.pos + offset + 1);
+        let mut depth = 1;
+        let mut end_pos = body_start;
+        while end_pos < self.tokens.len() && depth > 0 {
+            match self.tokens[end_pos] {
+                Token::CacheKeyword => depth += 1,
+                Token::EndCacheKeyword => depth -= 1,
+                _ => {}
+            }
+            end_pos += 1;
+        }
+        if depth > 0 {
+            self.pos = self.tokens.len();
+            return Err(format!("Missing endcache for the cache directive at token {}", start));
+        }
+        let body = self.tokens[body_start..end_pos - 1].to_vec();
+        
+        let key = self.parse_cache_key();
+        self.pos = end_pos;
+        let key = match (key, self.options.fragment_cache.clone()) {
+            (Ok((key, ttl)), Some(cache)) => {
+                // The same block renders differently for each locale. Keys cannot hold
+                // a NUL, so a localized key never equals a key written in a template.
+                let key = match self.data.get("locale") {
+                    Some(Obj::Str(locale)) => format!("{}\0{}", locale, key),
+                    _ => key,
+                };
+                Some((key, ttl, cache))
+            },
+            (Ok(_), None) => None,
+            (Err(e), _) => {
+                // Still render the body, just without caching it
+                self.report_error(format!("Error in cache key: {}", e))?;
+                None
+            },
+        };
+        
+        if let Some((key, _, cache)) = &key
+            && let Some(fragment) = cache.get(key) {
+            self.write_html(&fragment);
+            return Ok(());
+        }
+        
+        // Render the body unminified, so the fragment can be written through the minifier
+        // of whichever render uses it
+        let mut body_compiler = self.sub_compiler(body);
+        body_compiler.options.minify = false;
+        let fragment = body_compiler.generate_output()?;
+        self.errors += body_compiler.errors;
+        // A fragment with errors in it is not worth keeping
+        if let Some((key, ttl, cache)) = key
+            && body_compiler.errors == 0 {
+            cache.set(&key, fragment.clone(), ttl);
+        }
+        self.write_html(&fragment);
+        Ok(())
+    }
+    
+    /// Parses the key expression and optional `ttl seconds` of a `cache` directive
+    fn parse_cache_key(&mut self) -> Result<(String, Option<Duration>), String> {
+        let key = self.parse_expression(0)?.interal_value_as_string();
+        if key.contains('\0') {
+            return Err("Cache keys cannot contain a NUL character".to_string());
+        }
+        if !matches!(self.tokens.get(self.pos), Some(Token::Identifier(word)) if word == "ttl") {
+            return Ok((key, None));
+        }
+        self.pos += 1;
+        match self.parse_expression(0)? {
+            Obj::Numerical(seconds) if seconds >= 0.0 => Ok((key, Some(Duration::from_secs_f64(seconds)))),
+            other => Err(format!("Cache ttl must be a number of seconds, found {}", other.type_of())),
+        }
+    }
+    
+    /// Calls a built-in function; the position is on the opening parenthesis
+    fn handle_function_call(&mut self, name: &str) -> Result<Obj, String> {
+        self.pos += 1; // Skip the left parenthesis
+        let (positional, named) = self.parse_arguments(&Token::RightParen)?;
+        match name {
+            "_" => self.translate(positional, named),
+            _ if ESCAPE_FUNCTIONS.contains(&name) => {
+                let [value] = positional.as_slice() else {
+                    return Err(format!("{}() takes exactly one argument", name));
+                };
+                Ok(Obj::Str(escape::apply(name, &value.interal_value_as_string()).unwrap_or_default()))
+            },
+            _ => Err(format!("Unknown function '{}'", name)),
+        }
+    }
+    
+    /// Parses comma separated arguments up to and including `closing`.
+    /// `name = value` arguments are returned separately from positional ones.
+    fn parse_arguments(&mut self, closing: &Token) -> Result<Arguments, String> {
+        let mut positional = Vec::new();
+        let mut named = Vec::new();
+        
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(token) if token == closing => {
+                    self.pos += 1;
+                    return Ok((positional, named));
+                },
+                None => return Err("Unexpected end of input in arguments".to_string()),
+                _ => {}
+            }
+            
+            if let (Some(Token::Identifier(name)), Some(Token::Assignment)) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+                let name = name.clone();
+                self.pos += 2;
+                named.push((name, self.parse_expression(0)?));
+            } else {
+                positional.push(self.parse_expression(0)?);
+            }
+            
+            match self.tokens.get(self.pos) {
+                Some(Token::Comma) => self.pos += 1,
+                Some(token) if token == closing => {},
+                // The last argument of a directive already consumed its end of statement
+                _ if closing == &Token::EndOfStatement && self.tokens.get(self.pos - 1) == Some(closing) => {
+                    return Ok((positional, named));
+                },
+                _ => return Err("Expected ',' between arguments".to_string()),
+            }
+        }
+    }
+    
+    /// Looks up a message for `_(key, ...)` and `trans` in the catalog and fills in its placeholders.
+    ///
+    /// The locale is the `locale` variable of the context. The plural form follows the `count`
+    /// argument, or else the first positional argument after the key when it is a number.
+    /// Without a catalog or a translation, the key itself is the message.
+    fn translate(&self, positional: Vec<Obj>, named: Vec<(String, Obj)>) -> Result<Obj, String> {
+        let mut positional = positional.into_iter();
+        let key = match positional.next() {
+            Some(Obj::Str(key)) => key,
+            Some(other) => return Err(format!("Message key must be a string, found {}", other.type_of())),
+            None => return Err("Expected a message key".to_string()),
+        };
+        let positional: Vec<Obj> = positional.collect();
+        
+        let count = named.iter()
+            .find(|(name, _)| name == "count")
+            .map(|(_, value)| value)
+            .or(positional.first())
+            .and_then(|value| match value {
+                Obj::Numerical(n) => Some(*n),
+                _ => None,
+            });
+        let locale = match self.data.get("locale") {
+            Some(Obj::Str(locale)) => locale.as_str(),
+            _ => "",
+        };
+        
+        let message = self.options.catalog.as_ref()
+            .and_then(|catalog| catalog.translate(locale, &key, count))
+            .unwrap_or(key);
+        Ok(Obj::Str(format_message(&message, &positional, &named)))
+    }
+    
     fn handle_if_statement(&mut self) -> Result<(), String> {
         self.pos += 1; // Skip if keyword
         
@@ -549,14 +934,10 @@ impl TemplateCompiler {
                         self.data.insert(loop_var.clone(), item.clone());
                         
                         // Execute the loop body
-                        let mut body_compiler = TemplateCompiler::new(
-                            loop_body.clone(),
-                            self.data.clone()
-                        );
+                        let mut body_compiler = self.sub_compiler(loop_body.clone());
                         match body_compiler.generate_output() {
                             Ok(body_output) => {
-                                self.output.push_str(&body_output);
-                                self.data = body_compiler.data;
+                                self.absorb(body_compiler, &body_output);
                             },
                             Err(e) => return Err(e),
                         }
@@ -576,14 +957,10 @@ impl TemplateCompiler {
                         self.data.insert(loop_var.clone(), Obj::Dict(entry));
                         
                         // Execute the loop body
-                        let mut body_compiler = TemplateCompiler::new(
-                            loop_body.clone(),
-                            self.data.clone()
-                        );
+                        let mut body_compiler = self.sub_compiler(loop_body.clone());
                         match body_compiler.generate_output() {
                             Ok(body_output) => {
-                                self.output.push_str(&body_output);
-                                self.data = body_compiler.data;
+                                self.absorb(body_compiler, &body_output);
                             },
                             Err(e) => return Err(e),
                         }
@@ -597,23 +974,19 @@ impl TemplateCompiler {
                         self.data.insert(loop_var.clone(), Obj::Numerical(i as f64));
                         
                         // Execute the loop body
-                        let mut body_compiler = TemplateCompiler::new(
-                            loop_body.clone(),
-                            self.data.clone()
-                        );
+                        let mut body_compiler = self.sub_compiler(loop_body.clone());
                         match body_compiler.generate_output() {
                             Ok(body_output) => {
-                                self.output.push_str(&body_output);
-                                self.data = body_compiler.data;
+                                self.absorb(body_compiler, &body_output);
                             },
                             Err(e) => return Err(e),
                         }
                     }
                 },
                 _ => {
-                    self.output.push_str(
-                        &format!("<!-- For loop requires a list, dictionary, or number, got {} -->", collection.type_of())
-                    );
+                    self.report_error(
+                        format!("For loop requires a list, dictionary, or number, got {}", collection.type_of())
+                    )?;
                 }
             }
             
@@ -658,24 +1031,20 @@ impl TemplateCompiler {
             }
             
             // Execute the loop body
-            let mut body_compiler = TemplateCompiler::new(
-                loop_body.clone(),
-                self.data.clone()
-            );
+            let mut body_compiler = self.sub_compiler(loop_body.clone());
             match body_compiler.generate_output() {
                 Ok(body_output) => {
-                    self.output.push_str(&body_output);
-                    self.data = body_compiler.data;
+                    self.absorb(body_compiler, &body_output);
                 },
                 Err(e) => {
-                    self.output.push_str(&format!("<!-- Error in while loop: {} -->", e));
+                    self.report_error(format!("Error in while loop: {}", e))?;
                     break; // Don't halt rendering on error
                 }
             }
             
             iteration += 1;
             if iteration == MAX_ITERATIONS {
-                self.output.push_str("<!-- While loop exceeded maximum iterations - possible infinite loop -->");
+                self.report_error("While loop exceeded maximum iterations - possible infinite loop".to_string())?;
                 break;
             }
         }
@@ -686,73 +1055,12 @@ impl TemplateCompiler {
         Ok(())
     }
     
-    fn process_token(&mut self) -> Result<(), String> {
-        match &self.tokens[self.pos] {
-            Token::HtmlContent(content) => {
-                self.output.push_str(content);
-                self.pos += 1;
-            },
-            Token::OutputKeyword => {
-                self.pos += 1;
-                match self.evaluate_expression() {
-                    Ok(value) => self.output.push_str(&value.interal_value_as_string()),
-                    Err(e) => self.output.push_str(&format!("<!-- Error evaluating expression: {} -->", e))
-                }
-            },
-            Token::LetKeyword => {
-                if let Err(e) = self.handle_assignment() {
-                    self.output.push_str(&format!("<!-- Error in assignment: {} -->", e));
-                }
-            },
-            Token::IfKeyword => {
-                if let Err(e) = self.handle_if_statement() {
-                    self.output.push_str(&format!("<!-- Error in if statement: {} -->", e));
-                }
-            },
-            Token::ForKeyword => {
-                if let Err(e) = self.handle_for_loop() {
-                    self.output.push_str(&format!("<!-- Error in for loop: {} -->", e));
-                }
-            },
-            Token::WhileKeyword => {
-                if let Err(e) = self.handle_while_loop() {
-                    self.output.push_str(&format!("<!-- Error in while loop: {} -->", e));
-                }
-            },
-            Token::Identifier(name) => {
-                let var_name = name.clone();
-                self.pos += 1;
-                
-                if let Err(e) = self.handle_variable_access(&var_name, true) {
-                    self.output.push_str(&format!("<!-- Error accessing variable {}: {} -->", var_name, e));
-                }
-            },
-            Token::EndOfStatement => {
-                self.pos += 1;
-            },
-            _ => {
-                // Skip other tokens
-                self.pos += 1;
-            }
-        }
-        Ok(())
-    }
-    
     fn evaluate_condition(&mut self) -> Result<bool, String> {
         match self.evaluate_expression() {
-            Ok(expr_value) => {
-                match expr_value {
-                    Obj::Boolean(b) => Ok(b),
-                    Obj::Numerical(n) => Ok(n != 0.0),
-                    Obj::Str(s) => Ok(!s.is_empty()),
-                    Obj::List(l) => Ok(!l.is_empty()),
-                    Obj::Dict(d) => Ok(!d.is_empty()),
-                    Obj::None => Ok(false),
-                }
-            },
+            Ok(expr_value) => Ok(self.is_truthy(&expr_value)),
             Err(e) => {
                 // Instead of failing, return false for invalid conditions
-                self.output.push_str(&format!("<!-- Error in condition: {} -->", e));
+                self.report_error(format!("Error in condition: {}", e))?;
                 Ok(false)
             }
         }
@@ -764,9 +1072,37 @@ impl TemplateCompiler {
     
     // A recursive descent parser for expressions
     fn parse_expression(&mut self, precedence: u8) -> Result<Obj, String> {
-        let mut left = self.parse_primary()?;
+        let left = self.parse_operand(precedence)?;
+        
+        if matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement)) {
+            self.pos += 1;
+        }
+        
+        Ok(left)
+    }
+    
+    /// Parses an expression like `parse_expression`, but stops in front of the token after it 
+    /// instead of consuming the end of the directive 
+    fn parse_operand(&mut self, precedence: u8) -> Result<Obj, String> {
+        let start = self.pos;
+        let mut left = match self.parse_primary() {
+            Ok(value) => value,
+            Err(e) => {
+                // A failed lookup is a missing value for the null-coalescing operator
+                self.pos = start;
+                if !self.is_null_coalescing() {
+                    return Err(e);
+                }
+                self.skip_operand()?;
+                Obj::None
+            }
+        };
         
         while self.pos < self.tokens.len() {
+            if matches!(self.tokens[self.pos], Token::LogicalNot)
+                && matches!(self.tokens.get(self.pos + 1), Some(Token::InKeyword)) {
+                return Err("Use `not in` to test that a value is not in a collection".to_string());
+            }
             let current_precedence = self.get_operator_precedence();
             
             if current_precedence <= precedence {
@@ -776,10 +1112,6 @@ impl TemplateCompiler {
             left = self.parse_binary_op(left, current_precedence)?;
         }
         
-        if matches!(self.tokens.get(self.pos), Some(Token::EndOfStatement)) {
-            self.pos += 1;
-        }
-        
         Ok(left)
     }
     
@@ -792,7 +1124,23 @@ impl TemplateCompiler {
             Token::Object(obj) => {
                 let value = obj.clone();
                 self.pos += 1;
-                Ok(value)
+                self.parse_access_chain(value)
+            },
+            Token::Error(message) => Err(message.clone()),
+            Token::InterpolatedString(parts) => {
+                let parts = parts.clone();
+                self.pos += 1;
+                
+                let mut value = String::new();
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => value.push_str(&text),
+                        StringPart::Expression(tokens) => {
+                            value.push_str(&self.evaluate_interpolation(tokens)?.interal_value_as_string());
+                        }
+                    }
+                }
+                self.parse_access_chain(Obj::Str(value))
             },
             Token::Identifier(name) => {
                 let var_name = name.clone();
@@ -812,13 +1160,7 @@ impl TemplateCompiler {
                     self.pos += 1;
                     
                     // After a parenthesized expression, check if there's property access or indexing
-                    if matches!(self.tokens.get(self.pos), Some(Token::Dot)) {
-                        self.handle_property_access(expr)
-                    } else if matches!(self.tokens.get(self.pos), Some(Token::LeftSquareBracket)) {
-                        self.handle_indexing(expr)
-                    } else {
-                        Ok(expr)
-                    }
+                    self.parse_access_chain(expr)
                 } else {
                     Err("Expected closing parenthesis".to_string())
                 }
@@ -836,15 +1178,175 @@ impl TemplateCompiler {
                 self.pos += 1;
                 let value = self.parse_expression(100)?; // High precedence for unary operators
                 
-                match value {
-                    Obj::Boolean(b) => Ok(Obj::Boolean(!b)),
-                    _ => Ok(Obj::Boolean(false)), // Any non-boolean value is treated as falsy
-                }
+                Ok(Obj::Boolean(!self.is_truthy(&value)))
+            },
+            Token::NotKeyword => {
+                self.pos += 1;
+                // Between `and` (20) and `==` (30), so `not x == 1` is `not (x == 1)`
+                let value = self.parse_expression(25)?;
+                
+                Ok(Obj::Boolean(!self.is_truthy(&value)))
             },
             _ => Err(format!("Unexpected token in expression: {:?}", self.tokens[self.pos])),
         }
     }
     
+    /// Evaluates the expression of one `${...}` interpolation against the current data
+    fn evaluate_interpolation(&mut self, tokens: Vec<Token>) -> Result<Obj, String> {
+        if tokens.is_empty() {
+            return Err("Empty interpolation in string literal".to_string());
+        }
+        
+        // Evaluate in place so the expression sees the same data without cloning it
+        let len = tokens.len();
+        let outer_tokens = std::mem::replace(&mut self.tokens, tokens);
+        let outer_pos = self.pos;
+        self.pos = 0;
+        let result = self.evaluate_expression();
+        let complete = self.pos == len;
+        self.tokens = outer_tokens;
+        self.pos = outer_pos;
+        
+        match result {
+            Ok(_) if !complete => Err("Unexpected token in string interpolation".to_string()),
+            result => result,
+        }
+    }
+    
+    /// Applies the `[index]`, `.property` and `?.property` accessors that follow a value.
+    ///
+    /// `?.` yields None instead of an error when the value is None, and None then passes
+    /// through the rest of the chain unchanged. On any other value it reports the same errors as `.`.
+    fn parse_access_chain(&mut self, mut value: Obj) -> Result<Obj, String> {
+        let mut short_circuited = false;
+        
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(Token::LeftSquareBracket) if short_circuited => self.skip_delimited()?,
+                Some(Token::Dot) if short_circuited => self.pos += 2,
+                Some(Token::LeftSquareBracket) => value = self.handle_indexing(value)?,
+                Some(Token::Dot) => value = self.handle_property_access(value)?,
+                Some(Token::OptionalDot) => {
+                    if !matches!(self.tokens.get(self.pos + 1), Some(Token::Identifier(_))) {
+                        return Err("Expected identifier after ?.".to_string());
+                    }
+                    if short_circuited || value.is_none() {
+                        self.pos += 2;
+                        value = Obj::None;
+                    } else {
+                        value = self.handle_property_access(value)?;
+                    }
+                    short_circuited = value.is_none();
+                },
+                _ => return Ok(value),
+            }
+        }
+    }
+    
+    /// Moves past an access chain without evaluating it
+    fn skip_access_chain(&mut self) -> Result<(), String> {
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(Token::LeftSquareBracket) => self.skip_delimited()?,
+                Some(Token::Dot) | Some(Token::OptionalDot) => {
+                    self.pos += 1;
+                    if matches!(self.tokens.get(self.pos), Some(Token::Identifier(_))) {
+                        self.pos += 1;
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+    
+    /// Moves past a parenthesized or bracketed group, including nested groups
+    fn skip_delimited(&mut self) -> Result<(), String> {
+        let mut depth = 0;
+        
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(Token::LeftParen) | Some(Token::LeftSquareBracket) => depth += 1,
+                Some(Token::RightParen) | Some(Token::RightSquareBracket) => depth -= 1,
+                Some(Token::EndOfStatement) | None => {
+                    return Err("Unbalanced brackets in expression".to_string());
+                },
+                _ => {}
+            }
+            self.pos += 1;
+            
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+    
+    /// Moves past an operand and every binary operator binding tighter than `precedence`,
+    /// mirroring `parse_expression` without evaluating anything. Used for the branches
+    /// that conditional operators do not take.
+    fn skip_expression(&mut self, precedence: u8) -> Result<(), String> {
+        self.skip_operand()?;
+        
+        loop {
+            let current_precedence = self.get_operator_precedence();
+            if current_precedence <= precedence {
+                return Ok(());
+            }
+            
+            match self.tokens[self.pos] {
+                Token::QuestionMark => {
+                    self.pos += 1;
+                    self.skip_expression(0)?;
+                    self.expect_colon()?;
+                    self.skip_expression(current_precedence - 1)?;
+                },
+                Token::Range | Token::RangeInclusive => {
+                    self.pos += 1;
+                    self.skip_expression(current_precedence)?;
+                    if matches!(self.tokens.get(self.pos), Some(Token::Identifier(word)) if word == "step") {
+                        self.pos += 1;
+                        self.skip_expression(current_precedence)?;
+                    }
+                },
+                // `not in`
+                Token::NotKeyword => {
+                    self.pos += 2;
+                    self.skip_expression(current_precedence)?;
+                },
+                _ => {
+                    self.pos += 1;
+                    self.skip_expression(current_precedence)?;
+                }
+            }
+        }
+    }
+    
+    /// Moves past prefix operators, a primary and its access chain
+    fn skip_operand(&mut self) -> Result<(), String> {
+        while matches!(self.tokens.get(self.pos), Some(Token::Minus) | Some(Token::LogicalNot) | Some(Token::NotKeyword)) {
+            self.pos += 1;
+        }
+        match self.tokens.get(self.pos) {
+            Some(Token::Identifier(_)) if matches!(self.tokens.get(self.pos + 1), Some(Token::LeftParen)) => {
+                self.pos += 1;
+                self.skip_delimited()?;
+            },
+            Some(Token::Object(_)) | Some(Token::InterpolatedString(_)) | Some(Token::Identifier(_)) => self.pos += 1,
+            Some(Token::LeftParen) => self.skip_delimited()?,
+            Some(token) => return Err(format!("Unexpected token in expression: {:?}", token)),
+            None => return Err("Unexpected end of input while parsing expression".to_string()),
+        }
+        self.skip_access_chain()
+    }
+    
+    fn expect_colon(&mut self) -> Result<(), String> {
+        if matches!(self.tokens.get(self.pos), Some(Token::Colon)) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err("Expected ':' in conditional expression".to_string())
+        }
+    }
+    
     // Handle property access with dot notation
     fn handle_property_access(&mut self, obj: Obj) -> Result<Obj, String> {
         self.pos += 1; // Skip the dot
@@ -996,29 +1498,124 @@ impl TemplateCompiler {
             Token::LogicalAnd => {
                 self.pos += 1;
                 
-                // Short-circuit evaluation
+                // Short-circuit evaluation, the right operand is skipped but not evaluated
                 if !self.is_truthy(&left) {
+                    self.skip_expression(precedence)?;
                     return Ok(Obj::Boolean(false));
                 }
                 
                 let right = self.parse_expression(precedence)?;
-                Ok(Obj::Boolean(self.is_truthy(&left) && self.is_truthy(&right)))
+                Ok(Obj::Boolean(self.is_truthy(&right)))
             },
             Token::LogicalOr => {
                 self.pos += 1;
                 
-                // Short-circuit evaluation
+                // Short-circuit evaluation, the right operand is skipped but not evaluated
                 if self.is_truthy(&left) {
+                    self.skip_expression(precedence)?;
                     return Ok(Obj::Boolean(true));
                 }
                 
                 let right = self.parse_expression(precedence)?;
-                Ok(Obj::Boolean(self.is_truthy(&left) || self.is_truthy(&right)))
+                Ok(Obj::Boolean(self.is_truthy(&right)))
+            },
+            Token::InKeyword => {
+                self.pos += 1;
+                let right = self.parse_expression(precedence)?;
+                Ok(Obj::Boolean(right.contains(&left)))
+            },
+            Token::NotKeyword => {
+                // Only reached for `not in`, see get_operator_precedence
+                self.pos += 2;
+                let right = self.parse_expression(precedence)?;
+                Ok(Obj::Boolean(!right.contains(&left)))
+            },
+            Token::QuestionMark => {
+                self.pos += 1;
+                
+                // Only the selected branch is evaluated; the other is skipped
+                if self.is_truthy(&left) {
+                    let value = self.parse_expression(0)?;
+                    self.expect_colon()?;
+                    self.skip_expression(precedence - 1)?;
+                    Ok(value)
+                } else {
+                    self.skip_expression(0)?;
+                    self.expect_colon()?;
+                    // Right-associative, so `a ? b : c ? d : e` nests in the else branch
+                    self.parse_expression(precedence - 1)
+                }
+            },
+            Token::NullCoalesce => {
+                self.pos += 1;
+                
+                if left.is_none() {
+                    self.parse_expression(precedence)
+                } else {
+                    self.skip_expression(precedence)?;
+                    Ok(left)
+                }
             },
+            Token::Range => self.parse_range(left, false, precedence),
+            Token::RangeInclusive => self.parse_range(left, true, precedence),
             _ => Err(format!("Unknown operator: {:?}", self.tokens[self.pos])),
         }
     }
     
+    /// Parses the right-hand side of `start..end` or `start..=end` with an optional
+    /// `step n` clause, and expands it into a list of numbers.
+    fn parse_range(&mut self, start: Obj, inclusive: bool, precedence: u8) -> Result<Obj, String> {
+        self.pos += 1; // Skip the range operator
+        let end = self.parse_operand(precedence)?;
+        
+        // `step` is only a keyword as the token right after the range end, so it stays usable 
+        // as a variable name everywhere else, including at the start of the next directive.
+        let step = if matches!(self.tokens.get(self.pos), Some(Token::Identifier(word)) if word == "step") {
+            self.pos += 1;
+            self.parse_operand(precedence)?
+        } else {
+            Obj::Numerical(1.0)
+        };
+        
+        self.build_range(start, end, step, inclusive)
+    }
+    
+    /// Expands a range into a list. The direction follows the bounds, so `10..0`
+    /// counts down; only the magnitude of `step` is used.
+    fn build_range(&self, start: Obj, end: Obj, step: Obj, inclusive: bool) -> Result<Obj, String> {
+        const MAX_RANGE_LENGTH: usize = 100000; // Safety limit
+        
+        let (start, end) = match (start, end) {
+            (Obj::Numerical(s), Obj::Numerical(e)) => (s, e),
+            _ => return Err("Range bounds must be numbers".to_string()),
+        };
+        let step = match step {
+            Obj::Numerical(n) if n != 0.0 && n.is_finite() => n.abs(),
+            Obj::Numerical(_) => return Err("Range step must be a non-zero number".to_string()),
+            _ => return Err("Range step must be a number".to_string()),
+        };
+        let step = if start <= end { step } else { -step };
+        
+        let mut items = Vec::new();
+        loop {
+            let current = start + step * items.len() as f64;
+            let within = if step > 0.0 {
+                current < end || (inclusive && current == end)
+            } else {
+                current > end || (inclusive && current == end)
+            };
+            if !within {
+                break;
+            }
+            if items.len() == MAX_RANGE_LENGTH {
+                return Err(format!("Range exceeds {} items", MAX_RANGE_LENGTH));
+            }
+            items.push(Obj::Numerical(current));
+        }
+        
+        Ok(Obj::List(items))
+    }
+    
     fn apply_binary_op<F>(&self, left: Obj, right: Obj, op: F) -> Result<Obj, String>
     where
         F: Fn(f64, f64) -> f64,
@@ -1089,10 +1686,16 @@ impl TemplateCompiler {
         }
         
         match self.tokens[self.pos] {
+            Token::QuestionMark => 5,
+            Token::NullCoalesce => 7,
             Token::LogicalOr => 10,
             Token::LogicalAnd => 20,
             Token::EqualsEquals | Token::NotEquals => 30,
             Token::LessThan | Token::LessThanEquals | Token::GreaterThan | Token::GreaterThanEquals => 40,
+            Token::InKeyword => 40,
+            // `not` is prefix-only, except as the first half of `not in`
+            Token::NotKeyword if matches!(self.tokens.get(self.pos + 1), Some(Token::InKeyword)) => 40,
+            Token::Range | Token::RangeInclusive => 45,
             Token::Plus | Token::Minus => 50,
             Token::Multiply | Token::Divide | Token::Modulus => 60,
             Token::Exponent => 70,
@@ -1102,14 +1705,9 @@ impl TemplateCompiler {
         }
     }
     
+    /// The single truthiness rule shared by `if`, `while`, `?:`, `!`/`not`, `&&`/`and` and `||`/`or`.
+    /// `false`, `0`, `""`, `[]`, `{}` and None are falsy, everything else is truthy.
     fn is_truthy(&self, value: &Obj) -> bool {
-        match value {
-            Obj::Boolean(b) => *b,
-            Obj::Numerical(n) => *n != 0.0,
-            Obj::Str(s) => !s.is_empty(),
-            Obj::List(l) => !l.is_empty(),
-            Obj::Dict(d) => !d.is_empty(),
-            Obj::None => false,
-        }
+        value.boolean()
     }
 } 
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use crate::Value as Obj;
+
+/// The variables a template is rendered with.
+///
+/// Besides plain values, a context can hold lazy values: closures that only run when a
+/// template first reads the variable. The result is memoized, also across renders sharing
+/// the context, so an expensive query runs at most once and only if it is needed.
+///
+/// ```rust
+/// use akari::{Context, TemplateManager, Value};
+///
+/// let mut context = Context::new();
+/// context.insert("show_sidebar", false);
+/// context.insert_lazy("sidebar", || Value::new("expensive query result"));
+///
+/// let manager = TemplateManager::new("");
+/// let template = "-[ if show_sidebar ]--[ sidebar ]--[ endif ]-".to_string();
+/// assert_eq!(manager.render_string_with(template, &context, manager.options()).unwrap().output, "");
+/// assert!(!context.is_evaluated("sidebar"));
+/// ```
+#[derive(Clone, Default)]
+pub struct Context {
+    values: HashMap<String, Obj>,
+    lazy: HashMap<String, Arc<LazyValue>>,
+    /// Lazy variables removed with `del`, which must not come back on the next read
+    removed: HashSet<String>,
+}
+
+struct LazyValue {
+    compute: Box<dyn Fn() -> Obj + Send + Sync>,
+    value: OnceLock<Obj>,
+}
+
+impl LazyValue {
+    fn force(&self) -> &Obj {
+        self.value.get_or_init(|| (self.compute)())
+    }
+}
+
+impl Context {
+    /// Creates an empty context
+    pub fn new() -> Self {
+        Context::default()
+    }
+
+    /// Sets a variable to a value
+    pub fn insert<K: Into<String>, V: Into<Obj>>(&mut self, name: K, value: V) {
+        let name = name.into();
+        self.removed.remove(&name);
+        self.values.insert(name, value.into());
+    }
+
+    /// Sets a variable to a value computed by `compute` the first time a template reads it
+    pub fn insert_lazy<K, F>(&mut self, name: K, compute: F)
+    where
+        K: Into<String>,
+        F: Fn() -> Obj + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.values.remove(&name);
+        self.removed.remove(&name);
+        self.lazy.insert(name, Arc::new(LazyValue { compute: Box::new(compute), value: OnceLock::new() }));
+    }
+
+    /// Reads a variable, computing it first if it is lazy
+    pub fn get(&self, name: &str) -> Option<&Obj> {
+        if let Some(value) = self.values.get(name) {
+            return Some(value);
+        }
+        if self.removed.contains(name) {
+            return None;
+        }
+        self.lazy.get(name).map(|lazy| lazy.force())
+    }
+
+    /// Reads a variable for modification, computing it first if it is lazy
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Obj> {
+        if !self.values.contains_key(name) {
+            let value = self.get(name)?.clone();
+            self.values.insert(name.to_string(), value);
+        }
+        self.values.get_mut(name)
+    }
+
+    /// Whether the variable is set, without computing it
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.values.contains_key(name) || (self.lazy.contains_key(name) && !self.removed.contains(name))
+    }
+
+    /// Removes a variable, returning its value if it was set and computed
+    pub fn remove(&mut self, name: &str) -> Option<Obj> {
+        if self.lazy.contains_key(name) {
+            self.removed.insert(name.to_string());
+        }
+        let value = self.values.remove(name);
+        value.or_else(|| self.lazy.get(name).and_then(|lazy| lazy.value.get().cloned()))
+    }
+
+    /// Whether a lazy variable has been computed. Plain values count as computed.
+    pub fn is_evaluated(&self, name: &str) -> bool {
+        self.values.contains_key(name) || self.lazy.get(name).is_some_and(|lazy| lazy.value.get().is_some())
+    }
+
+    /// The variables that have a value, leaving out lazy variables that were never computed
+    pub fn into_values(self) -> HashMap<String, Obj> {
+        let mut values = self.values;
+        for (name, lazy) in self.lazy {
+            if self.removed.contains(&name) {
+                continue;
+            }
+            if let Some(value) = lazy.value.get() {
+                values.entry(name).or_insert_with(|| value.clone());
+            }
+        }
+        values
+    }
+}
+
+impl From<HashMap<String, Obj>> for Context {
+    fn from(values: HashMap<String, Obj>) -> Self {
+        Context { values, ..Context::default() }
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lazy: HashMap<&String, Option<&Obj>> = self.lazy.iter()
+            .filter(|(name, _)| !self.removed.contains(*name) && !self.values.contains_key(*name))
+            .map(|(name, lazy)| (name, lazy.value.get()))
+            .collect();
+        f.debug_struct("Context")
+            .field("values", &self.values)
+            .field("lazy", &lazy)
+            .finish()
+    }
+}
@@ -0,0 +1,298 @@
+/// The functions templates can call to escape a value, plus `raw` which returns it unchanged.
+/// An `output` consisting of a single call to one of them is never escaped again automatically.
+pub const ESCAPE_FUNCTIONS: [&str; 7] = ["escape_html", "escape_attr", "escape_url", "escape_js", "escape_css", "urlencode", "raw"];
+
+/// URL attributes whose value is a whole URL, checked with `escape_url`
+const URL_ATTRIBUTES: [&str; 10] = ["href", "src", "action", "formaction", "cite", "poster", "background", "data", "srcset", "xlink:href"];
+
+/// Applies the escape function `name` to `value`, or returns None for other names
+pub fn apply(name: &str, value: &str) -> Option<String> {
+    match name {
+        "escape_html" => Some(escape_html(value)),
+        "escape_attr" => Some(escape_attr(value)),
+        "escape_url" => Some(escape_url(value)),
+        "escape_js" => Some(escape_js(value)),
+        "escape_css" => Some(escape_css(value)),
+        "urlencode" => Some(urlencode(value)),
+        "raw" => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Escapes `&`, `<`, `>`, `"` and `'` for HTML text and quoted attribute values
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Escapes every ASCII character except letters and digits as `&#xHH;`,
+/// which is safe in quoted and unquoted attribute values alike
+pub fn escape_attr(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if ch.is_ascii() && !ch.is_ascii_alphanumeric() {
+            escaped.push_str(&format!("&#x{:02X};", ch as u32));
+        } else {
+            escaped.push(ch);
+        }
+    }
+    escaped
+}
+
+/// Makes a whole URL safe for an attribute: characters that may not appear in a URL are
+/// percent-encoded, and `javascript:`, `vbscript:` and `data:` URLs are replaced by `#`
+pub fn escape_url(value: &str) -> String {
+    let scheme: String = value.trim_start()
+        .chars()
+        .take_while(|c| *c != ':' && *c != '/' && *c != '?' && *c != '#')
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if value.contains(':') && ["javascript", "vbscript", "data"].contains(&scheme.as_str()) {
+        return "#".to_string();
+    }
+    percent_encode(value, |byte| byte.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&()*+,;=%".contains(&byte))
+}
+
+/// Percent-encodes everything but the unreserved characters `A-Z a-z 0-9 - . _ ~`,
+/// for a value placed inside a URL such as a query parameter
+pub fn urlencode(value: &str) -> String {
+    percent_encode(value, |byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
+}
+
+fn percent_encode(value: &str, keep: impl Fn(u8) -> bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if keep(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Escapes a value for use inside a JavaScript string literal. ASCII punctuation
+/// becomes `\xHH`, so the result can neither end the string nor the `<script>` element.
+pub fn escape_js(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' | '_' | ',' | '.' => escaped.push(ch),
+            '\u{2028}' | '\u{2029}' => escaped.push_str(&format!("\\u{:04X}", ch as u32)),
+            _ if ch.is_ascii() => escaped.push_str(&format!("\\x{:02X}", ch as u32)),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Escapes a value for use in a CSS property value or string. ASCII punctuation
+/// becomes a hex escape terminated by a space, e.g. `<` is `\3C `.
+pub fn escape_css(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if ch.is_ascii() && !ch.is_ascii_alphanumeric() {
+            escaped.push_str(&format!("\\{:X} ", ch as u32));
+        } else {
+            escaped.push(ch);
+        }
+    }
+    escaped
+}
+
+/// Where in an HTML document a value is being written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscapeContext {
+    /// Text content, comments and `<textarea>`/`<title>`
+    Html,
+    /// Inside a tag, or an ordinary attribute value
+    Attribute,
+    /// At the start of a URL attribute value such as `href`
+    Url,
+    /// Further into a URL attribute value, e.g. after `?q=`
+    UrlComponent,
+    /// A `<script>` element or an `on...` event handler attribute
+    Script,
+    /// A `<style>` element or a `style` attribute
+    Style,
+}
+
+impl EscapeContext {
+    /// Escapes a value for this context
+    pub fn escape(&self, value: &str) -> String {
+        match self {
+            EscapeContext::Html => escape_html(value),
+            EscapeContext::Attribute => escape_attr(value),
+            EscapeContext::Url => escape_url(value),
+            EscapeContext::UrlComponent => urlencode(value),
+            EscapeContext::Script => escape_js(value),
+            EscapeContext::Style => escape_css(value),
+        }
+    }
+}
+
+/// Follows the HTML written so far closely enough to tell which escaper a value needs.
+///
+/// This is not a full HTML parser: it tracks tags, attribute names and values,
+/// comments, and the raw text of `<script>`, `<style>`, `<textarea>` and `<title>`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HtmlContext {
+    state: State,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+enum State {
+    #[default]
+    Text,
+    TagName { name: String, closing: bool },
+    InTag { tag: String, closing: bool },
+    AttributeName { tag: String, name: String },
+    AfterAttributeName { tag: String, name: String },
+    BeforeValue { tag: String, name: String },
+    Value { tag: String, name: String, quote: Option<char>, empty: bool },
+    RawText { tag: String, matched: usize },
+    Comment { dashes: usize },
+}
+
+impl HtmlContext {
+    /// The context a value written now would land in
+    pub fn current(&self) -> EscapeContext {
+        match &self.state {
+            State::Text | State::Comment { .. } => EscapeContext::Html,
+            State::RawText { tag, .. } => match tag.as_str() {
+                "script" => EscapeContext::Script,
+                "style" => EscapeContext::Style,
+                _ => EscapeContext::Html,
+            },
+            State::BeforeValue { name, .. } => attribute_context(name, true),
+            State::Value { name, empty, .. } => attribute_context(name, *empty),
+            _ => EscapeContext::Attribute,
+        }
+    }
+
+    /// Advances past text written to the output
+    pub fn feed(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.step(ch);
+        }
+    }
+
+    fn step(&mut self, ch: char) {
+        let state = std::mem::take(&mut self.state);
+        self.state = match state {
+            State::Text if ch == '<' => State::TagName { name: String::new(), closing: false },
+            State::Text => State::Text,
+            State::TagName { name, .. } if name.is_empty() && ch == '/' => State::TagName { name, closing: true },
+            State::TagName { name, closing } => {
+                if ch == '>' {
+                    end_of_tag(name, closing)
+                } else if ch.is_whitespace() || ch == '/' {
+                    State::InTag { tag: name, closing }
+                } else if name.is_empty() && !closing && !ch.is_ascii_alphabetic() && ch != '!' {
+                    State::Text // A lone `<` in text
+                } else {
+                    let name = name + &ch.to_lowercase().to_string();
+                    if name == "!--" { State::Comment { dashes: 0 } } else { State::TagName { name, closing } }
+                }
+            },
+            State::InTag { tag, closing } => {
+                if ch == '>' {
+                    end_of_tag(tag, closing)
+                } else if ch.is_whitespace() || ch == '/' {
+                    State::InTag { tag, closing }
+                } else {
+                    State::AttributeName { tag, name: ch.to_lowercase().to_string() }
+                }
+            },
+            State::AttributeName { tag, name } => {
+                if ch == '>' {
+                    end_of_tag(tag, false)
+                } else if ch == '=' {
+                    State::BeforeValue { tag, name }
+                } else if ch.is_whitespace() {
+                    State::AfterAttributeName { tag, name }
+                } else {
+                    State::AttributeName { tag, name: name + &ch.to_lowercase().to_string() }
+                }
+            },
+            State::AfterAttributeName { tag, name } => {
+                if ch == '>' {
+                    end_of_tag(tag, false)
+                } else if ch == '=' {
+                    State::BeforeValue { tag, name }
+                } else if ch.is_whitespace() {
+                    State::AfterAttributeName { tag, name }
+                } else {
+                    State::AttributeName { tag, name: ch.to_lowercase().to_string() }
+                }
+            },
+            State::BeforeValue { tag, name } => {
+                if ch == '>' {
+                    end_of_tag(tag, false)
+                } else if ch == '"' || ch == '\'' {
+                    State::Value { tag, name, quote: Some(ch), empty: true }
+                } else if ch.is_whitespace() {
+                    State::BeforeValue { tag, name }
+                } else {
+                    State::Value { tag, name, quote: None, empty: false }
+                }
+            },
+            State::Value { tag, name, quote, .. } => match quote {
+                Some(quote) if ch == quote => State::InTag { tag, closing: false },
+                None if ch.is_whitespace() => State::InTag { tag, closing: false },
+                None if ch == '>' => end_of_tag(tag, false),
+                _ => State::Value { tag, name, quote, empty: false },
+            },
+            State::RawText { tag, matched } => {
+                // Looking for `</tag`, case-insensitively
+                let expected = format!("</{}", tag);
+                let next = expected[matched..].chars().next();
+                if next.is_some_and(|next| next.eq_ignore_ascii_case(&ch)) {
+                    if matched + 1 == expected.len() {
+                        State::TagName { name: tag, closing: true }
+                    } else {
+                        State::RawText { tag, matched: matched + 1 }
+                    }
+                } else {
+                    State::RawText { tag, matched: if ch == '<' { 1 } else { 0 } }
+                }
+            },
+            State::Comment { dashes } => match ch {
+                '-' => State::Comment { dashes: dashes + 1 },
+                '>' if dashes >= 2 => State::Text,
+                _ => State::Comment { dashes: 0 },
+            },
+        };
+    }
+}
+
+/// The state after the `>` of a tag; the content of raw text elements is not HTML
+fn end_of_tag(tag: String, closing: bool) -> State {
+    match tag.as_str() {
+        "script" | "style" | "textarea" | "title" if !closing => State::RawText { tag, matched: 0 },
+        _ => State::Text,
+    }
+}
+
+fn attribute_context(name: &str, at_start: bool) -> EscapeContext {
+    if name.starts_with("on") {
+        EscapeContext::Script
+    } else if name == "style" {
+        EscapeContext::Style
+    } else if URL_ATTRIBUTES.contains(&name) {
+        if at_start { EscapeContext::Url } else { EscapeContext::UrlComponent }
+    } else {
+        EscapeContext::Attribute
+    }
+}
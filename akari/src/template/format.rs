@@ -0,0 +1,225 @@
+use super::parse::{Delimiters, Token, tokenize_with, tokenize_with_spans};
+
+/// Settings for [`format_template`]
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// The directive markers of the source
+    pub delimiters: Delimiters,
+    /// Indent lines holding nothing but directives by this much per enclosing
+    /// `if`/`for`/`while`/`block`/`cache`. The indentation before a directive is template
+    /// text, so this changes whitespace in the rendered output. Off when None.
+    pub indent: Option<String>,
+}
+
+/// Reformats a template source.
+///
+/// Inside every directive, tokens are separated by single spaces around binary operators
+/// and keywords, and by none around `.`, `..`, brackets and calls, e.g. `-[if(a>1)]-` becomes
+/// `-[ if (a > 1) ]-`. Each token keeps its own spelling. Template text, `verbatim` regions
+/// and an unclosed directive at the end are kept byte for byte, apart from the indentation
+/// of directive lines when [`FormatOptions::indent`] is set.
+///
+/// Without `indent`, the result tokenizes exactly like the source, so it renders the same.
+/// An error is returned rather than a source that would tokenize differently.
+///
+/// ```rust
+/// use akari::{format_template, FormatOptions};
+/// let source = "-[for x in 1..=3]-<li>-[output x*2]-</li>-[endfor]-";
+/// assert_eq!(
+///     format_template(source, &FormatOptions::default()).unwrap(),
+///     "-[ for x in 1..=3 ]-<li>-[ output x * 2 ]-</li>-[ endfor ]-"
+/// );
+/// ```
+pub fn format_template(source: &str, options: &FormatOptions) -> Result<String, String> {
+    let pieces = split(source, &options.delimiters);
+    let spaced: String = pieces.iter().map(|piece| piece.text.as_str()).collect();
+    if tokenize_with(spaced.as_str(), &options.delimiters) != tokenize_with(source, &options.delimiters) {
+        return Err("Formatting would change how the template is read, so it was left alone".to_string());
+    }
+    match &options.indent {
+        Some(indent) => Ok(reindent(pieces, indent)),
+        None => Ok(spaced),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Text,
+    /// A verbatim region or unclosed directive, never reindented
+    Raw,
+    /// A directive, opening (+1), closing (-1) or neither (0) a nested section
+    Directive(i32),
+}
+
+struct Piece {
+    kind: Kind,
+    text: String,
+}
+
+/// Splits the source into template text and formatted directives
+fn split(source: &str, delimiters: &Delimiters) -> Vec<Piece> {
+    let mut tokens = tokenize_with_spans(source, delimiters).into_iter().peekable();
+    let mut pieces = Vec::new();
+    // The end of the source covered by the pieces so far
+    let mut covered = 0;
+
+    while let Some((token, span)) = tokens.next() {
+        if let Token::HtmlContent(_) = token {
+            // A verbatim region starts with a marker, template text never does
+            let text = &source[span.clone()];
+            let kind = if text.starts_with(delimiters.open.as_str()) { Kind::Raw } else { Kind::Text };
+            if covered < span.start {
+                pieces.push(Piece { kind: Kind::Raw, text: source[covered..span.start].to_string() });
+            }
+            pieces.push(Piece { kind, text: text.to_string() });
+            covered = span.end;
+            continue;
+        }
+
+        // A directive, from its first token up to and including its EndOfStatement
+        let mut directive = vec![(token, span)];
+        while !matches!(directive.last(), Some((Token::EndOfStatement, _))) {
+            match tokens.next() {
+                Some(next) => directive.push(next),
+                None => break,
+            }
+        }
+        let (_, close) = directive.pop().unwrap_or((Token::EndOfStatement, source.len()..source.len()));
+        let first = directive.first().map_or(close.start, |(_, span)| span.start);
+        // The opening marker is the last one before the first token, as only whitespace separates them
+        let start = covered + source[covered..first].rfind(delimiters.open.as_str()).unwrap_or(0);
+        if covered < start {
+            // An empty verbatim region, which has no token
+            pieces.push(Piece { kind: Kind::Raw, text: source[covered..start].to_string() });
+        }
+        if close.is_empty() {
+            pieces.push(Piece { kind: Kind::Raw, text: source[start..].to_string() });
+            covered = source.len();
+            break;
+        }
+        covered = close.end;
+
+        let directive: Vec<(Token, &str)> = directive.into_iter()
+            .map(|(token, span)| (token, &source[span]))
+            .collect();
+        let depth_change = match directive.first() {
+            Some((Token::IfKeyword | Token::ForKeyword | Token::WhileKeyword | Token::BlockKeyword | Token::CacheKeyword, _)) => 1,
+            Some((Token::EndIfKeyword | Token::EndForKeyword | Token::EndWhileKeyword | Token::EndBlockKeyword | Token::EndCacheKeyword, _)) => -1,
+            _ => 0,
+        };
+        let content = join_tokens(&directive);
+        let text = if content.is_empty() {
+            format!("{} {}", delimiters.open, delimiters.close)
+        } else {
+            format!("{} {} {}", delimiters.open, content, delimiters.close)
+        };
+        pieces.push(Piece { kind: Kind::Directive(depth_change), text });
+    }
+    if covered < source.len() {
+        // An empty verbatim region at the end
+        pieces.push(Piece { kind: Kind::Raw, text: source[covered..].to_string() });
+    }
+    pieces
+}
+
+/// Writes the tokens of a directive with normalized spacing, each in its source spelling
+fn join_tokens(tokens: &[(Token, &str)]) -> String {
+    let mut result = String::new();
+    // Whether the previous token was a prefix operator, which binds to what follows
+    let mut prefix = false;
+    for (i, (token, text)) in tokens.iter().enumerate() {
+        let previous = if i > 0 { Some(&tokens[i - 1].0) } else { None };
+        let operand_before = previous.is_some_and(is_operand_end);
+        let space = match (previous, token) {
+            (None, _) => false,
+            // A prefix minus before a digit would be read as a negative number
+            _ if prefix => matches!(token, Token::Object(_)) && text.starts_with(|c: char| c.is_ascii_digit()),
+            (Some(Token::Dot | Token::OptionalDot | Token::Range | Token::RangeInclusive), _) => false,
+            (_, Token::Dot | Token::OptionalDot | Token::Range | Token::RangeInclusive) => false,
+            (Some(Token::LeftParen | Token::LeftSquareBracket), _) => false,
+            (_, Token::RightParen | Token::RightSquareBracket | Token::Comma) => false,
+            // Calls and indexing
+            (_, Token::LeftParen) => !matches!(previous, Some(Token::Identifier(_) | Token::RightParen)),
+            (_, Token::LeftSquareBracket) => !operand_before,
+            // Postfix `x++`
+            (_, Token::Increment | Token::Decrement) => !operand_before,
+            _ => true,
+        };
+        if space {
+            result.push(' ');
+        }
+        result.push_str(text);
+        prefix = match token {
+            Token::Minus | Token::LogicalNot => !operand_before,
+            Token::Increment | Token::Decrement => !operand_before,
+            _ => false,
+        };
+    }
+    result
+}
+
+/// Whether a token can end an operand, making a following `-`, `[` or `++` binary, indexing or postfix
+fn is_operand_end(token: &Token) -> bool {
+    match token {
+        Token::Identifier(name) => name.starts_with(|c: char| c.is_alphanumeric() || c == '_'),
+        Token::Object(_) | Token::InterpolatedString(_) | Token::RightParen | Token::RightSquareBracket
+        | Token::Increment | Token::Decrement => true,
+        _ => false,
+    }
+}
+
+/// Indents lines that hold nothing but directives (and whitespace) by their nesting depth
+fn reindent(pieces: Vec<Piece>, indent: &str) -> String {
+    // Split into lines, each a list of pieces without newlines except at the end
+    let mut lines: Vec<Vec<Piece>> = vec![Vec::new()];
+    for piece in pieces {
+        if piece.kind == Kind::Text {
+            for part in piece.text.split_inclusive('\n') {
+                lines.last_mut().unwrap().push(Piece { kind: Kind::Text, text: part.to_string() });
+                if part.ends_with('\n') {
+                    lines.push(Vec::new());
+                }
+            }
+        } else {
+            // Raw text may span lines, but stays on the line it starts on
+            let ends_line = piece.text.ends_with('\n');
+            lines.last_mut().unwrap().push(piece);
+            if ends_line {
+                lines.push(Vec::new());
+            }
+        }
+    }
+
+    let mut result = String::new();
+    let mut depth: i32 = 0;
+    for line in lines {
+        let directive_only = line.iter().any(|piece| matches!(piece.kind, Kind::Directive(_)))
+            && line.iter().all(|piece| match piece.kind {
+                Kind::Text => piece.text.trim().is_empty(),
+                Kind::Raw => false,
+                Kind::Directive(_) => true,
+            });
+        // Closing directives at the start of a line are indented like their opening one
+        let mut lowest = 0;
+        let mut change = 0;
+        for piece in &line {
+            if let Kind::Directive(delta) = piece.kind {
+                change += delta;
+                lowest = lowest.min(change);
+            }
+        }
+        if directive_only {
+            result.push_str(&indent.repeat((depth + lowest).max(0) as usize));
+            let first_directive = line.iter().position(|piece| matches!(piece.kind, Kind::Directive(_))).unwrap_or(0);
+            for piece in &line[first_directive..] {
+                result.push_str(&piece.text);
+            }
+        } else {
+            for piece in &line {
+                result.push_str(&piece.text);
+            }
+        }
+        depth = (depth + change).max(0);
+    }
+    result
+}
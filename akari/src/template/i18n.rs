@@ -0,0 +1,267 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::Value as Obj;
+use super::parse::{Token, StringPart};
+
+/// The plural categories a message may provide forms for
+const PLURAL_CATEGORIES: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
+/// A source of translated messages for the `trans` directive and the `_(...)` expression.
+///
+/// Implement this to serve messages from a database, gettext files or anything else;
+/// [`MessageCatalog`] covers JSON files.
+pub trait Catalog: Send + Sync {
+    /// Returns the message for `key` in `locale`, or None if there is no translation.
+    /// `count` is given when the caller wants a plural form, and the catalog picks the
+    /// form matching it. Placeholders in the returned message are substituted afterwards.
+    fn translate(&self, locale: &str, key: &str, count: Option<f64>) -> Option<String>;
+}
+
+/// An in-memory catalog of messages per locale, usually loaded from JSON.
+///
+/// Each locale maps keys to messages. A message is either a string or, when it has
+/// plural forms, an object mapping plural categories (`zero`, `one`, `two`, `few`,
+/// `many`, `other`) to strings. Nested objects are flattened into dotted keys.
+///
+/// ```json
+/// {
+///     "en": {
+///         "nav": { "home": "Home" },
+///         "greeting": "Hello, {name}!",
+///         "items": { "one": "{count} item", "other": "{count} items" }
+///     }
+/// }
+/// ```
+///
+/// Lookups fall back from a regional locale (`pt-BR`) to its language (`pt`)
+/// and then to the default locale.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageCatalog {
+    default_locale: String,
+    locales: HashMap<String, HashMap<String, Obj>>,
+}
+
+impl MessageCatalog {
+    /// Creates an empty catalog falling back to `default_locale`
+    pub fn new<S: Into<String>>(default_locale: S) -> Self {
+        MessageCatalog {
+            default_locale: default_locale.into(),
+            locales: HashMap::new(),
+        }
+    }
+
+    /// Loads a JSON file mapping locales to their messages
+    pub fn from_jsonf<P: AsRef<str>, S: Into<String>>(path: P, default_locale: S) -> Result<Self, String> {
+        let mut catalog = MessageCatalog::new(default_locale);
+        match Obj::from_jsonf(path.as_ref())? {
+            Obj::Dict(locales) => {
+                for (locale, messages) in locales {
+                    catalog = catalog.with_messages(locale, messages);
+                }
+                Ok(catalog)
+            },
+            other => Err(format!("Expected a catalog object mapping locales to messages, found {}", other.type_of())),
+        }
+    }
+
+    /// Adds the messages of one locale from a JSON file holding an object of messages
+    pub fn with_jsonf<L: Into<String>, P: AsRef<str>>(self, locale: L, path: P) -> Result<Self, String> {
+        let messages = Obj::from_jsonf(path.as_ref())?;
+        if !matches!(messages, Obj::Dict(_)) {
+            return Err(format!("Expected an object of messages in {}", path.as_ref()));
+        }
+        Ok(self.with_messages(locale, messages))
+    }
+
+    /// Adds messages for a locale, merging with the ones it already has
+    pub fn with_messages<L: Into<String>>(mut self, locale: L, messages: Obj) -> Self {
+        let entries = self.locales.entry(locale.into()).or_default();
+        flatten_messages("", messages, entries);
+        self
+    }
+
+    /// The locales that have messages
+    pub fn locales(&self) -> BTreeSet<String> {
+        self.locales.keys().cloned().collect()
+    }
+
+    fn lookup(&self, locale: &str, key: &str) -> Option<(&str, &Obj)> {
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        [locale, language, self.default_locale.as_str()]
+            .into_iter()
+            .find_map(|candidate| {
+                let (locale, messages) = self.locales.get_key_value(candidate)?;
+                messages.get(key).map(|message| (locale.as_str(), message))
+            })
+    }
+}
+
+impl Catalog for MessageCatalog {
+    fn translate(&self, locale: &str, key: &str, count: Option<f64>) -> Option<String> {
+        let (found_locale, message) = self.lookup(locale, key)?;
+        match message {
+            Obj::Dict(forms) => {
+                let category = match count {
+                    // An explicit zero form wins over the language's rule
+                    Some(n) if n == 0.0 && forms.contains_key("zero") => "zero",
+                    Some(n) => plural_category(found_locale, n),
+                    None => "other",
+                };
+                forms.get(category)
+                    .or_else(|| forms.get("other"))
+                    .map(|form| form.interal_value_as_string())
+            },
+            message => Some(message.interal_value_as_string()),
+        }
+    }
+}
+
+/// Flattens nested message objects into dotted keys, keeping plural form objects whole
+fn flatten_messages(prefix: &str, messages: Obj, entries: &mut HashMap<String, Obj>) {
+    match messages {
+        Obj::Dict(dict) if !prefix.is_empty() && is_plural_forms(&dict) => {
+            entries.insert(prefix.to_string(), Obj::Dict(dict));
+        },
+        Obj::Dict(dict) => {
+            for (key, value) in dict {
+                let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                flatten_messages(&key, value, entries);
+            }
+        },
+        message => {
+            entries.insert(prefix.to_string(), message);
+        }
+    }
+}
+
+fn is_plural_forms(dict: &HashMap<String, Obj>) -> bool {
+    dict.contains_key("other") && dict.keys().all(|key| PLURAL_CATEGORIES.contains(&key.as_str()))
+}
+
+/// Returns the CLDR plural category of `n` in `locale`: one of
+/// `zero`, `one`, `two`, `few`, `many` and `other`.
+///
+/// Covers the common language families; unknown languages use the English rule.
+pub fn plural_category(locale: &str, n: f64) -> &'static str {
+    let language = locale.split(['-', '_']).next().unwrap_or(locale).to_ascii_lowercase();
+    let integer = n.fract() == 0.0;
+    let i = n.abs().trunc() as u64;
+    let (mod10, mod100) = (i % 10, i % 100);
+
+    match language.as_str() {
+        "ja" | "zh" | "ko" | "vi" | "th" | "id" | "ms" | "lo" | "my" => "other",
+        "fr" | "pt" | "hi" | "bn" | "fa" | "am" | "zu" => {
+            if i <= 1 { "one" } else { "other" }
+        },
+        "ru" | "uk" | "be" => {
+            if !integer {
+                "other"
+            } else if mod10 == 1 && mod100 != 11 {
+                "one"
+            } else if (2..=4).contains(&mod10) && !(12..=14).contains(&mod100) {
+                "few"
+            } else {
+                "many"
+            }
+        },
+        "pl" => {
+            if !integer {
+                "other"
+            } else if i == 1 {
+                "one"
+            } else if (2..=4).contains(&mod10) && !(12..=14).contains(&mod100) {
+                "few"
+            } else {
+                "many"
+            }
+        },
+        "cs" | "sk" => {
+            if !integer {
+                "many"
+            } else if i == 1 {
+                "one"
+            } else if (2..=4).contains(&i) {
+                "few"
+            } else {
+                "other"
+            }
+        },
+        "ar" => {
+            if !integer {
+                "other"
+            } else if i == 0 {
+                "zero"
+            } else if i == 1 {
+                "one"
+            } else if i == 2 {
+                "two"
+            } else if (3..=10).contains(&mod100) {
+                "few"
+            } else if (11..=99).contains(&mod100) {
+                "many"
+            } else {
+                "other"
+            }
+        },
+        _ => {
+            if integer && i == 1 { "one" } else { "other" }
+        }
+    }
+}
+
+/// Substitutes `{name}` placeholders with named arguments and `{0}`, `{1}`, ...
+/// with positional ones. Unknown placeholders are left as they are.
+pub fn format_message(message: &str, positional: &[Obj], named: &[(String, Obj)]) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut rest = message;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &after[..end];
+        let value = match name.parse::<usize>() {
+            Ok(index) => positional.get(index),
+            Err(_) => named.iter().find(|(key, _)| key == name).map(|(_, value)| value),
+        };
+        match value {
+            Some(value) => result.push_str(&value.interal_value_as_string()),
+            None => result.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Collects the message keys used by `trans` directives and `_(...)` calls
+/// with a literal key, including those inside interpolated strings
+pub fn translatable_keys(tokens: &[Token]) -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    collect_keys(tokens, &mut keys);
+    keys
+}
+
+fn collect_keys(tokens: &[Token], keys: &mut BTreeSet<String>) {
+    for (i, token) in tokens.iter().enumerate() {
+        let key = match token {
+            Token::TransKeyword => tokens.get(i + 1),
+            Token::Identifier(name) if name == "_" && tokens.get(i + 1) == Some(&Token::LeftParen) => tokens.get(i + 2),
+            Token::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expression(expression) = part {
+                        collect_keys(expression, keys);
+                    }
+                }
+                None
+            },
+            _ => None,
+        };
+        if let Some(Token::Object(Obj::Str(key))) = key {
+            keys.insert(key.clone());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+/// Elements whose content is written exactly as it is
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Elements next to which whitespace never renders, so it can be dropped rather than collapsed
+const BLOCK_ELEMENTS: [&str; 54] = [
+    "!doctype", "html", "head", "body", "title", "meta", "link", "base", "script", "style", "noscript",
+    "template", "div", "p", "ul", "ol", "li", "dl", "dt", "dd", "table", "caption", "colgroup", "col",
+    "thead", "tbody", "tfoot", "tr", "td", "th", "section", "article", "aside", "header", "footer", "nav",
+    "main", "h1", "h2", "h3", "h4", "h5", "h6", "form", "fieldset", "legend", "hr", "br", "pre",
+    "blockquote", "figure", "figcaption", "details", "summary",
+];
+
+/// A streaming HTML minifier.
+///
+/// Text is fed in chunks as it is rendered and the minified result is appended to the
+/// output directly, keeping only a tag name and a little state between chunks.
+/// Runs of whitespace collapse to one space, or disappear next to block-level tags and at
+/// the start and end of the document. Comments are removed. Whitespace inside tags
+/// collapses too, while quoted attribute values and the contents of `<pre>`, `<textarea>`,
+/// `<script>` and `<style>` are kept as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Minifier {
+    mode: Mode,
+    /// Whitespace seen but not written yet, since whether it matters depends on what follows
+    pending_space: bool,
+    previous: Previous,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Mode {
+    #[default]
+    Text,
+    /// After `<`, collecting the tag name before anything is written
+    TagOpen { name: String },
+    Tag { name: String, quote: Option<char> },
+    Comment { dashes: usize },
+    Raw { tag: String, matched: usize },
+}
+
+/// What was written last, deciding whether pending whitespace matters
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Previous {
+    #[default]
+    Start,
+    Text,
+    Tag(String),
+}
+
+impl Minifier {
+    /// Minifies a chunk of HTML into `output`
+    pub fn write(&mut self, html: &str, output: &mut String) {
+        for ch in html.chars() {
+            self.step(ch, output);
+        }
+    }
+
+    /// Completes the document, dropping trailing whitespace
+    pub fn finish(&mut self, output: &mut String) {
+        if let Mode::TagOpen { name } = std::mem::take(&mut self.mode) {
+            self.flush_space(false, output);
+            output.push('<');
+            output.push_str(&name);
+        }
+        self.pending_space = false;
+    }
+
+    fn step(&mut self, ch: char, output: &mut String) {
+        self.mode = match std::mem::take(&mut self.mode) {
+            Mode::Text => {
+                if ch.is_whitespace() {
+                    self.pending_space = true;
+                    Mode::Text
+                } else if ch == '<' {
+                    Mode::TagOpen { name: String::new() }
+                } else {
+                    self.flush_space(false, output);
+                    output.push(ch);
+                    self.previous = Previous::Text;
+                    Mode::Text
+                }
+            },
+            Mode::TagOpen { mut name } => {
+                if name.is_empty() && !(ch.is_ascii_alphabetic() || ch == '/' || ch == '!') {
+                    // A lone `<` in text
+                    self.flush_space(false, output);
+                    output.push('<');
+                    self.previous = Previous::Text;
+                    self.mode = Mode::Text;
+                    return self.step(ch, output);
+                }
+                if ch.is_whitespace() || ch == '>' || (ch == '/' && !name.is_empty()) {
+                    self.flush_space(is_block(&name), output);
+                    output.push('<');
+                    output.push_str(&name);
+                    self.mode = Mode::Tag { name, quote: None };
+                    return self.step(ch, output);
+                }
+                name.push(ch);
+                if name == "!--" { Mode::Comment { dashes: 0 } } else { Mode::TagOpen { name } }
+            },
+            Mode::Tag { name, quote: Some(quote) } => {
+                output.push(ch);
+                Mode::Tag { name, quote: if ch == quote { None } else { Some(quote) } }
+            },
+            Mode::Tag { name, quote: None } => {
+                if ch.is_whitespace() {
+                    self.pending_space = true;
+                    Mode::Tag { name, quote: None }
+                } else if ch == '>' {
+                    self.pending_space = false;
+                    output.push('>');
+                    let mode = if RAW_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+                        Mode::Raw { tag: name.clone(), matched: 0 }
+                    } else {
+                        Mode::Text
+                    };
+                    self.previous = Previous::Tag(name);
+                    mode
+                } else {
+                    if std::mem::take(&mut self.pending_space) {
+                        output.push(' ');
+                    }
+                    output.push(ch);
+                    let quote = if ch == '"' || ch == '\'' { Some(ch) } else { None };
+                    Mode::Tag { name, quote }
+                }
+            },
+            Mode::Comment { dashes } => match ch {
+                '-' => Mode::Comment { dashes: dashes + 1 },
+                '>' if dashes >= 2 => Mode::Text,
+                _ => Mode::Comment { dashes: 0 },
+            },
+            Mode::Raw { tag, matched } => {
+                output.push(ch);
+                // Looking for `</tag`, case-insensitively
+                let expected = format!("</{}", tag);
+                let next = expected[matched..].chars().next();
+                if next.is_some_and(|next| next.eq_ignore_ascii_case(&ch)) {
+                    if matched + 1 == expected.len() {
+                        Mode::Tag { name: format!("/{}", tag), quote: None }
+                    } else {
+                        Mode::Raw { tag, matched: matched + 1 }
+                    }
+                } else {
+                    Mode::Raw { tag, matched: if ch == '<' { 1 } else { 0 } }
+                }
+            },
+        };
+    }
+
+    /// Writes pending whitespace as one space unless it sits at the start or next to a block-level tag
+    fn flush_space(&mut self, next_is_block: bool, output: &mut String) {
+        if !std::mem::take(&mut self.pending_space) {
+            return;
+        }
+        let significant = match &self.previous {
+            Previous::Start => false,
+            Previous::Text => !next_is_block,
+            Previous::Tag(name) => !next_is_block && !is_block(name),
+        };
+        if significant {
+            output.push(' ');
+        }
+    }
+}
+
+fn is_block(name: &str) -> bool {
+    BLOCK_ELEMENTS.contains(&name.trim_start_matches('/').to_ascii_lowercase().as_str())
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// The kind of document a template produces, deciding how errors are rendered inline,
+/// how values are escaped when auto-escaping is on, and whether minification applies.
+///
+/// [`TemplateManager`](crate::TemplateManager) picks the mode from the extension of the
+/// rendered template, see [`mode_for_extension`].
+#[derive(Clone, Default)]
+pub enum OutputMode {
+    /// Errors become `<!-- ... -->` comments, values are escaped for their HTML context
+    /// and the output can be minified
+    #[default]
+    Html,
+    /// Errors become `[Error: ...]`, values are written as they are and nothing is minified.
+    /// For emails, Markdown, YAML and other text.
+    Text,
+    /// Errors and escaping as defined by the format; nothing is minified
+    Custom(Arc<dyn OutputFormat>),
+}
+
+/// Error rendering and escaping for an [`OutputMode::Custom`] output
+pub trait OutputFormat: Send + Sync {
+    /// The text written in place of an error under [`ErrorPolicy::Inline`](crate::ErrorPolicy::Inline)
+    fn format_error(&self, message: &str) -> String;
+
+    /// Escapes a value written with auto-escaping on. Writes it unchanged by default.
+    fn escape(&self, value: &str) -> String {
+        value.to_string()
+    }
+}
+
+impl OutputMode {
+    /// Creates a `Custom` mode from a format
+    pub fn custom<F: OutputFormat + 'static>(format: F) -> Self {
+        OutputMode::Custom(Arc::new(format))
+    }
+
+    /// The text written in place of an error under `ErrorPolicy::Inline`
+    pub fn format_error(&self, message: &str) -> String {
+        match self {
+            OutputMode::Html => format!("<!-- {} -->", message),
+            OutputMode::Text => format!("[Error: {}]", message),
+            OutputMode::Custom(format) => format.format_error(message),
+        }
+    }
+
+    /// Whether this is the `Html` mode
+    pub fn is_html(&self) -> bool {
+        matches!(self, OutputMode::Html)
+    }
+}
+
+impl PartialEq for OutputMode {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OutputMode::Html, OutputMode::Html) | (OutputMode::Text, OutputMode::Text) => true,
+            (OutputMode::Custom(a), OutputMode::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputMode::Html => write!(f, "Html"),
+            OutputMode::Text => write!(f, "Text"),
+            OutputMode::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// The built-in mode for a file extension, compared case-insensitively: `Html` for
+/// `html`, `htm`, `xhtml`, `xml` and `svg`, `Text` for `txt`, `text`, `md`, `markdown`,
+/// `yaml`, `yml`, `eml`, `csv`, `tsv`, `ini` and `toml`, None for any other extension
+pub fn mode_for_extension(extension: &str) -> Option<OutputMode> {
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" | "xhtml" | "xml" | "svg" => Some(OutputMode::Html),
+        "txt" | "text" | "md" | "markdown" | "yaml" | "yml" | "eml" | "csv" | "tsv" | "ini" | "toml" => Some(OutputMode::Text),
+        _ => None,
+    }
+}
@@ -1,4 +1,7 @@
+use std::ops::Range; 
+
 use super::Value as Obj; 
+use super::source_map::SourceLocation; 
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token { 
@@ -25,14 +28,29 @@ pub enum Token {
     WhileKeyword,            // e.g., "while"
     EndWhileKeyword,         // e.g., "endwhile"
     DelKeyword,              // e.g., "del" 
+    TransKeyword,            // e.g., "trans" 
+    CacheKeyword,            // e.g., "cache" 
+    EndCacheKeyword,         // e.g., "endcache" 
+    AssertKeyword,           // e.g., "assert" 
+    DebugKeyword,            // e.g., "debug" 
+    LoadKeyword,             // e.g., "load" 
     
     // Literals and Identifiers
     Identifier(String),      // variable names or user-defined names
     Object(Obj),          // literal number, string, boolean, list and object 
     HtmlContent(String),     // HTML content such as "<script ...>...</script>"
+    InterpolatedString(Vec<StringPart>), // string literal containing ${...} expressions
     
     // Operators and Punctuation 
     Dot,                  // . (dot operator for object access) 
+    Range,                   // .. (exclusive range)
+    RangeInclusive,          // ..= (inclusive range)
+    OptionalDot,             // ?. (property access that tolerates None)
+    
+    // Conditional Operators
+    QuestionMark,            // ? (ternary condition)
+    Colon,                   // : (ternary branch separator)
+    NullCoalesce,            // ?? (fallback for None or missing values)
 
     // Assignment Operators
     Assignment,              // =
@@ -65,30 +83,141 @@ pub enum Token {
     // Logical Operators
     LogicalAnd,              // && (or "and")
     LogicalOr,               // || (or "or")
-    LogicalNot,              // !  (or "not")
+    LogicalNot,              // !
+    NotKeyword,              // not (looser than comparisons, and the first half of `not in`)
     
     // Grouping and Delimiters
     LeftParen,               // (
     RightParen,              // )
     LeftSquareBracket,       // [
     RightSquareBracket,      // ]
+    Comma,                   // , (separates call arguments)
     
     // End of Statement
     EndOfStatement,          // Marks end of a directive or statement 
+    
+    // Errors
+    Error(String),           // source the lexer cannot read, reported when the directive is rendered 
+    
+    // Debugging
+    Location(SourceLocation), // Where the following text or directive starts, see `tokenize_with_locations` 
+}
+
+/// A piece of an interpolated string literal such as `"Hello ${user.name}"`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringPart {
+    Text(String),            // literal text between interpolations
+    Expression(Vec<Token>),  // tokens of an expression inside ${...}
+}
+
+/// The markers that open and close a directive, `-[` and `]-` by default.
+///
+/// Other markers let akari template files where `-[` is meaningful, or read
+/// mustache-style sources with `Delimiters::try_new("{{", "}}")`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Delimiters {
+    pub open: String,
+    pub close: String,
+}
+
+impl Delimiters {
+    /// Creates a pair of directive markers, or an error if they would be misread.
+    ///
+    /// The markers must not be empty, equal to each other, or contain whitespace or `"`.
+    /// The closing marker must not start with something the lexer reads inside a directive:
+    /// with `%}` a directive ending in `a %}` could be a modulo, with `>>` a comparison and
+    /// with `]` an index. A closing bracket followed by another character, like the
+    /// default `]-`, is allowed; an index followed by that character then needs a space,
+    /// as in `list[0] - 1`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use akari::Delimiters;
+    /// assert!(Delimiters::try_new("{{", "}}").is_ok());
+    /// assert!(Delimiters::try_new("{%", "%}").is_err());
+    /// assert!(Delimiters::try_new("$$", "$$").is_err());
+    /// ```
+    pub fn try_new<O: Into<String>, C: Into<String>>(open: O, close: C) -> Result<Self, String> {
+        let delimiters = Delimiters { open: open.into(), close: close.into() };
+        if delimiters.open.is_empty() || delimiters.close.is_empty() {
+            return Err("Directive delimiters must not be empty".to_string());
+        }
+        if delimiters.open == delimiters.close {
+            return Err(format!("The opening and closing delimiters must differ, both are `{}`", delimiters.open));
+        }
+        for marker in [&delimiters.open, &delimiters.close] {
+            if marker.contains(|c: char| c.is_whitespace() || c == '"') {
+                return Err(format!("Directive delimiter `{}` must not contain whitespace or `\"`", marker));
+            }
+        }
+        if let Some(token) = delimiters.expression_prefix() {
+            return Err(format!(
+                "The closing delimiter `{}` starts with `{}`, which can also be part of an expression",
+                delimiters.close, token
+            ));
+        }
+        Ok(delimiters)
+    }
+
+    /// The start of the closing marker, if the lexer would read it as part of a directive
+    fn expression_prefix(&self) -> Option<&str> {
+        // Markers that never occur in the closing marker, so only its own text is lexed
+        let unmatched = Delimiters { open: "\0".to_string(), close: "\0".to_string() };
+        let mut lexer = Lexer::new(self.close.clone(), unmatched);
+        let token = lexer.lex_directive_token();
+        let prefix = &self.close[..lexer.pos];
+        match token {
+            // Characters without a meaning in directives
+            Token::Identifier(name) if !name.starts_with(|c: char| c.is_alphanumeric() || c == '_') => None,
+            // A closing bracket only ends a directive when the rest of the marker follows it
+            Token::RightSquareBracket | Token::RightParen
+                if !self.close[lexer.pos..].is_empty() && !self.close[lexer.pos..].starts_with([']', ')']) => None,
+            _ => Some(prefix),
+        }
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Delimiters { open: "-[".to_string(), close: "]-".to_string() }
+    }
 }
 
 /// The Lexer struct holds the input string (our template source code)
 /// and a current position pointer.
 pub struct Lexer {
-    input: String,
-    pos: usize,
+    pub(crate) input: String,
+    pub(crate) pos: usize,
+    delimiters: Delimiters,
 } 
 
 impl Lexer {
-    /// Creates a new Lexer instance from a given input.
-    /// The input can be any type convertible to a String.
-    pub fn new(input: String) -> Self {
-        Lexer { input, pos: 0 }
+    /// Creates a new Lexer instance from a given input,
+    /// recognizing the given directive markers.
+    pub fn new(input: String, delimiters: Delimiters) -> Self {
+        Lexer { input, pos: 0, delimiters }
+    }
+
+    /// Checks if the input (from the current position) starts with the directive opening marker.
+    pub fn peek_open(&self) -> bool {
+        self.peek_str(&self.delimiters.open)
+    }
+
+    /// Checks if the input (from the current position) starts with the directive closing marker.
+    pub fn peek_close(&self) -> bool {
+        self.peek_str(&self.delimiters.close)
+    }
+
+    /// Consumes an escaped marker in template text, `\-[` or `\]-`, returning the marker.
+    /// Returns None, without consuming anything, if no escaped marker starts here.
+    pub fn lex_escaped_marker(&mut self) -> Option<String> {
+        let rest = self.input[self.pos..].strip_prefix('\\')?;
+        let marker = [&self.delimiters.open, &self.delimiters.close].into_iter()
+            .find(|marker| rest.starts_with(marker.as_str()))?
+            .clone();
+        self.pos += 1 + marker.len();
+        Some(marker)
     }
 
     /// Returns the next character from the current position without consuming it.
@@ -133,30 +262,59 @@ impl Lexer {
         }
     }
 
+    /// Returns the position just past a `-[ keyword ]-` directive starting at `at`,
+    /// allowing any whitespace inside the markers.
+    fn match_directive(&self, at: usize, keyword: &str) -> Option<usize> {
+        let rest = self.input[at..].strip_prefix(self.delimiters.open.as_str())?;
+        let rest = rest.trim_start().strip_prefix(keyword)?;
+        let rest = rest.trim_start().strip_prefix(self.delimiters.close.as_str())?;
+        Some(self.input.len() - rest.len())
+    }
+
+    /// Lexes a `-[ verbatim ]- ... -[ endverbatim ]-` region if one starts at the current position.
+    ///
+    /// Everything between the two directives is returned untouched, including any
+    /// `-[` and `]-` markers. An unterminated region runs to the end of the input.
+    /// Returns None, without consuming anything, if the next directive is not `verbatim`.
+    pub fn lex_verbatim(&mut self) -> Option<String> {
+        let start = self.match_directive(self.pos, "verbatim")?;
+        let mut search = start;
+        while let Some(offset) = self.input[search..].find(self.delimiters.open.as_str()) {
+            let marker = search + offset;
+            if let Some(end) = self.match_directive(marker, "endverbatim") {
+                self.pos = end;
+                return Some(self.input[start..marker].to_string());
+            }
+            search = marker + self.delimiters.open.len();
+        }
+        self.pos = self.input.len();
+        Some(self.input[start..].to_string())
+    }
+
     /// This function lexes a directive block.
     ///
     /// A directive block starts with the marker "-[" (already consumed in the main loop)
     /// and ends with the marker "]-". It tokenizes the content inside (keywords, identifiers,
     /// literals, operators, etc.) and finally appends an `EndOfStatement` token.
-    pub fn lex_directive(&mut self) -> Vec<Token> {
+    /// Each token is paired with the byte range it was read from; the `EndOfStatement`
+    /// spans the closing marker, or is empty if the input ends first.
+    pub fn lex_directive(&mut self) -> Vec<(Token, Range<usize>)> {
         let mut tokens = Vec::new();
         self.skip_whitespace();
-        while self.pos < self.input.len() && !self.peek_str("]-") {
-            self.skip_whitespace();
-            if self.peek_str("]-") {
-                break;
-            }
+        while self.pos < self.input.len() && !self.peek_close() {
             // Lex one token at a time from within the directive
+            let start = self.pos;
             let token = self.lex_directive_token();
-            tokens.push(token);
+            tokens.push((token, start..self.pos));
             self.skip_whitespace();
         }
         // Consume the closing marker "]-" if present.
-        if self.peek_str("]-") {
-            self.pos += 2;
+        let close_start = self.pos;
+        if self.peek_close() {
+            self.pos += self.delimiters.close.len();
         }
         // Mark the end of the directive statement.
-        tokens.push(Token::EndOfStatement);
+        tokens.push((Token::EndOfStatement, close_start..self.pos));
         tokens
     }
 
@@ -167,7 +325,7 @@ impl Lexer {
     pub fn lex_directive_token(&mut self) -> Token {
         self.skip_whitespace();
         // If we have reached the directive closing marker, return an EndOfStatement.
-        if self.peek_str("]-") {
+        if self.peek_close() {
             return Token::EndOfStatement;
         }
         if let Some(ch) = self.peek() {
@@ -242,6 +400,24 @@ impl Lexer {
                 self.pos += 2;
                 return Token::LogicalOr;
             }
+            if self.peek_str("??") {
+                self.pos += 2;
+                return Token::NullCoalesce;
+            }
+            // `cond ?.5 : 1` is a ternary with a decimal, not optional chaining
+            if self.peek_str("?.") && !self.input[self.pos + 2..].starts_with(|c: char| c.is_ascii_digit()) {
+                self.pos += 2;
+                return Token::OptionalDot;
+            }
+            // Range operators, checked before the single dot used for property access.
+            if self.peek_str("..=") {
+                self.pos += 3;
+                return Token::RangeInclusive;
+            }
+            if self.peek_str("..") {
+                self.pos += 2;
+                return Token::Range;
+            }
             // If no two-character operator matches, check for single-character tokens.
             let ch = self.next_char().unwrap(); // safe because peek() returned Some(ch)
             match ch {
@@ -259,6 +435,9 @@ impl Lexer {
                 '[' => Token::LeftSquareBracket,
                 ']' => Token::RightSquareBracket,
                 '.' => Token::Dot, 
+                '?' => Token::QuestionMark,
+                ':' => Token::Colon,
+                ',' => Token::Comma,
                 // For any unrecognized character, we simply return it as an identifier.
                 _ => Token::Identifier(ch.to_string()),
             }
@@ -271,14 +450,29 @@ impl Lexer {
     ///
     /// It handles escape sequences such as \n, \t, \\ and \".
     /// The returned token wraps the resulting string into an Object (i.e. Obj::Str).
+    /// If the literal contains `${...}` it becomes an `InterpolatedString` instead,
+    /// whose expressions are evaluated at render time. `\$` produces a literal `$`.
+    /// A `${` without its closing brace makes the literal an `Error` token.
     pub fn lex_string(&mut self) -> Token {
         // Consume the opening double quote.
         self.next_char();
         let mut s = String::new();
+        let mut parts = Vec::new();
         while let Some(ch) = self.next_char() {
             if ch == '"' {
                 break;
             }
+            if ch == '$' && self.peek() == Some('{') {
+                self.next_char();
+                if !s.is_empty() {
+                    parts.push(StringPart::Text(std::mem::take(&mut s)));
+                }
+                match self.lex_interpolation() {
+                    Some(expression) => parts.push(StringPart::Expression(expression)),
+                    None => return Token::Error("Unclosed `${` in string literal, expected `}`".to_string()),
+                }
+                continue;
+            }
             if ch == '\\' {
                 if let Some(escaped) = self.next_char() {
                     match escaped {
@@ -294,12 +488,41 @@ impl Lexer {
                 s.push(ch);
             }
         }
-        // Wrap the literal string into an Object token.
-        Token::Object(Obj::Str(s))
+        if parts.is_empty() {
+            // Wrap the literal string into an Object token.
+            return Token::Object(Obj::Str(s));
+        }
+        if !s.is_empty() {
+            parts.push(StringPart::Text(s));
+        }
+        Token::InterpolatedString(parts)
+    }
+
+    /// Lexes the expression of a `${...}` interpolation up to its closing brace.
+    /// The opening `${` has already been consumed.
+    ///
+    /// Returns None when the input or the directive ends before the closing brace,
+    /// leaving the closing marker of the directive unconsumed.
+    fn lex_interpolation(&mut self) -> Option<Vec<Token>> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('}') => {
+                    self.next_char();
+                    return Some(tokens);
+                }
+                None => return None,
+                _ if self.peek_close() => return None,
+                _ => tokens.push(self.lex_directive_token()),
+            }
+        }
     }
 
     /// Lexes a numeric literal (which may be an integer or a floating point number).
     ///
+    /// A dot is only taken as a decimal point when a digit follows it, so `2..10`
+    /// lexes as a range rather than as the number `2.` followed by `.10`.
     /// The numeric literal is then wrapped into an Object token (i.e. Obj::Numerical).
     pub fn lex_number(&mut self) -> Token {
         let start = self.pos;
@@ -310,7 +533,7 @@ impl Lexer {
         while let Some(ch) = self.peek() {
             if ch.is_digit(10) {
                 self.next_char();
-            } else if ch == '.' && !dot_encountered {
+            } else if ch == '.' && !dot_encountered && self.peek_next_is_digit() {
                 dot_encountered = true;
                 self.next_char();
             } else {
@@ -331,8 +554,11 @@ impl Lexer {
     /// This function collects a contiguous string of alphanumeric characters or underscores.
     /// It then checks if the word matches a reserved keyword (such as "template", "block", etc.)
     /// or one of the boolean literals ("true", "false"). If not, it returns it as an Identifier token.
+    /// `trans`, `cache`, `endcache`, `assert`, `debug` and `load` are only keywords as the first
+    /// word of a directive, so templates can keep using them as variable names elsewhere.
     pub fn lex_identifier_or_keyword(&mut self) -> Token {
         let start = self.pos;
+        let leading = self.input[..start].trim_end().ends_with(self.delimiters.open.as_str());
         while let Some(ch) = self.peek() {
             if ch.is_alphanumeric() || ch == '_' {
                 self.next_char();
@@ -358,6 +584,9 @@ impl Lexer {
             "let" => Token::LetKeyword,
             "for" => Token::ForKeyword,
             "in" => Token::InKeyword,
+            "and" => Token::LogicalAnd,
+            "or" => Token::LogicalOr,
+            "not" => Token::NotKeyword,
             "if" => Token::IfKeyword,
             "output" => Token::OutputKeyword,
             "endif" => Token::EndIfKeyword,
@@ -365,6 +594,12 @@ impl Lexer {
             "while" => Token::WhileKeyword,
             "endwhile" => Token::EndWhileKeyword,
             "del" => Token::DelKeyword,
+            "trans" if leading => Token::TransKeyword,
+            "cache" if leading => Token::CacheKeyword,
+            "endcache" if leading => Token::EndCacheKeyword,
+            "assert" if leading => Token::AssertKeyword,
+            "debug" if leading => Token::DebugKeyword,
+            "load" if leading => Token::LoadKeyword,
             "match" => Token::MatchKeyword, 
             "endmatch" => Token::EndMatchKeyword, 
             "case" => Token::CaseKeyword, 
@@ -380,6 +615,10 @@ impl Lexer {
 /// and directive blocks (inside "-[" and "]-"). It accepts any input type that can be
 /// converted into a String (such as &str, String, or even Vec<u8> after conversion).
 ///
+/// To emit the markers literally, wrap the text in `-[ verbatim ]- ... -[ endverbatim ]-`,
+/// or escape a single marker as `\-[` or `\]-`. Inside a string literal both markers are
+/// plain text, so `-[ output "]-" ]-` prints `]-`; `\]-` and `\-[` work there as well.
+///
 /// # Example
 ///
 /// ```rust
@@ -408,26 +647,119 @@ impl Lexer {
 /// // with each directive ending with an EndOfStatement token.
 /// ```
 pub fn tokenize<S: Into<String>>(input: S) -> Vec<Token> {
-    let input_str = input.into();
-    let mut lexer = Lexer::new(input_str);
+    tokenize_with(input, &Delimiters::default())
+}
+
+/// Tokenizes the input like [`tokenize`], recognizing the given directive markers
+/// instead of `-[` and `]-`.
+///
+/// # Example
+///
+/// ```rust
+/// use akari::{tokenize_with, Delimiters, Token};
+/// let tokens = tokenize_with("| a | {{ output 1 + 1 }} |", &Delimiters::try_new("{{", "}}").unwrap());
+/// assert_eq!(tokens[0], Token::HtmlContent("| a | ".to_string()));
+/// assert_eq!(tokens[1], Token::OutputKeyword);
+/// ```
+pub fn tokenize_with<S: Into<String>>(input: S, delimiters: &Delimiters) -> Vec<Token> {
+    lex(input.into(), delimiters, None).into_iter().map(|(token, _)| token).collect()
+} 
+
+/// Tokenizes the input like [`tokenize_with`], adding a [`Token::Location`] before every
+/// directive and every line of template text, naming where in `template` it starts.
+/// The compiler uses them to build a [`SourceMap`](super::source_map::SourceMap).
+///
+/// # Example
+///
+/// ```rust
+/// use akari::{tokenize_with_locations, Delimiters, SourceLocation, Token};
+/// let tokens = tokenize_with_locations("a\n-[ x ]-", &Delimiters::default(), "page.html");
+/// let location = SourceLocation { template: "page.html".to_string(), line: 2, column: 1 };
+/// assert_eq!(tokens[2], Token::Location(location));
+/// assert_eq!(tokens[3], Token::Identifier("x".to_string()));
+/// ```
+pub fn tokenize_with_locations<S: Into<String>>(input: S, delimiters: &Delimiters, template: &str) -> Vec<Token> {
+    lex(input.into(), delimiters, Some(template)).into_iter().map(|(token, _)| token).collect()
+} 
+
+/// Tokenizes the input like [`tokenize_with`], pairing every token with the byte range of the
+/// source it was read from, e.g. for an editor to find the token under the cursor.
+/// Template text spans its source including escapes, so its content can be shorter.
+/// The `EndOfStatement` of a directive spans its closing marker, and is empty for a
+/// directive still open at the end of the input.
+///
+/// # Example
+///
+/// ```rust
+/// use akari::{tokenize_with_spans, Delimiters, Token};
+/// let tokens = tokenize_with_spans("<p>-[ name ]-", &Delimiters::default());
+/// assert_eq!(tokens[1], (Token::Identifier("name".to_string()), 6..10));
+/// assert_eq!(tokens[2], (Token::EndOfStatement, 11..13));
+/// ```
+pub fn tokenize_with_spans(input: &str, delimiters: &Delimiters) -> Vec<(Token, Range<usize>)> {
+    lex(input.to_string(), delimiters, None)
+} 
+
+/// Tokenizes the input, pairing each token with the byte range it was read from.
+/// With a template name, a [`Token::Location`] with an empty range goes before every
+/// directive and every line of template text.
+fn lex(input: String, delimiters: &Delimiters, template: Option<&str>) -> Vec<(Token, Range<usize>)> {
+    let mut lexer = Lexer::new(input, delimiters.clone());
     let mut tokens = Vec::new();
+    // Line and column of `tracked`, advanced as locations are needed
+    let (mut tracked, mut line, mut column) = (0, 1, 1);
+    let mut locate = |input: &str, pos: usize, tokens: &mut Vec<(Token, Range<usize>)>| {
+        let Some(template) = template else { return };
+        for ch in input[tracked..pos].chars() {
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        tracked = pos;
+        tokens.push((Token::Location(SourceLocation { template: template.to_string(), line, column }), pos..pos));
+    };
 
     // The main loop alternates between HTML mode and directive mode.
     while lexer.pos < lexer.input.len() {
         // When we see the directive start marker "-[", enter directive mode.
-        if lexer.peek_str("-[") {
-            lexer.pos += 2; // Consume the "-[" marker.
+        if lexer.peek_open() {
+            let start = lexer.pos;
+            // A verbatim region passes through as plain HTML content.
+            if let Some(html_content) = lexer.lex_verbatim() {
+                if !html_content.is_empty() {
+                    locate(&lexer.input, start, &mut tokens);
+                    tokens.push((Token::HtmlContent(html_content), start..lexer.pos));
+                }
+                continue;
+            }
+            locate(&lexer.input, start, &mut tokens);
+            lexer.pos += lexer.delimiters.open.len(); // Consume the "-[" marker.
             let directive_tokens = lexer.lex_directive();
             tokens.extend(directive_tokens);
         } else {
             // Otherwise, we are in HTML mode: collect text until the next "-[".
-            let start = lexer.pos;
-            while lexer.pos < lexer.input.len() && !lexer.peek_str("-[") {
-                lexer.next_char();
+            // An escaped marker "\-[" or "\]-" is kept as a literal "-[" or "]-".
+            // With locations, every line becomes its own token.
+            let mut html_content = String::new();
+            let mut start = lexer.pos;
+            while lexer.pos < lexer.input.len() && !lexer.peek_open() {
+                if let Some(marker) = lexer.lex_escaped_marker() {
+                    html_content.push_str(&marker);
+                } else if let Some(ch) = lexer.next_char() {
+                    html_content.push(ch);
+                    if ch == '\n' && template.is_some() && lexer.pos < lexer.input.len() && !lexer.peek_open() {
+                        locate(&lexer.input, start, &mut tokens);
+                        tokens.push((Token::HtmlContent(std::mem::take(&mut html_content)), start..lexer.pos));
+                        start = lexer.pos;
+                    }
+                }
             }
-            let html_content = lexer.input[start..lexer.pos].to_string();
             if !html_content.is_empty() {
-                tokens.push(Token::HtmlContent(html_content));
+                locate(&lexer.input, start, &mut tokens);
+                tokens.push((Token::HtmlContent(html_content), start..lexer.pos));
             }
         }
     }
@@ -0,0 +1,72 @@
+use std::ops::Range;
+
+/// A position in a template source: the template name and a 1-based line and column.
+/// Columns count characters, not bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub template: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Output bytes written by the template text or directive at `location`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapping {
+    pub output: Range<usize>,
+    pub location: SourceLocation,
+}
+
+/// Maps byte ranges of rendered output back to the templates that produced them,
+/// as returned in [`Compiled::source_map`](crate::Compiled::source_map) when [`RenderOptions::source_map`](crate::RenderOptions::source_map) is set.
+///
+/// Template text is mapped line by line, so each line of the output can be traced to its
+/// template line even after parents and partials are merged into one page. Values written by
+/// a directive map to the directive, and the output of a `cache` block to the `cache` directive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Non-overlapping ranges in output order
+    pub mappings: Vec<SourceMapping>,
+}
+
+impl SourceMap {
+    /// The location that wrote the output byte at `offset`
+    pub fn location_at(&self, offset: usize) -> Option<&SourceLocation> {
+        let index = self.mappings.partition_point(|mapping| mapping.output.end <= offset);
+        self.mappings.get(index)
+            .filter(|mapping| mapping.output.contains(&offset))
+            .map(|mapping| &mapping.location)
+    }
+
+    /// The location that wrote the start of the 1-based `line` of `output`
+    pub fn location_of_line(&self, output: &str, line: usize) -> Option<&SourceLocation> {
+        let offset = if line <= 1 {
+            0
+        } else {
+            output.match_indices('\n').nth(line - 2)?.0 + 1
+        };
+        self.location_at(offset)
+    }
+
+    /// Records that `output` was written from `location`, merging it into the previous
+    /// mapping when that one ends where this one starts and has the same location
+    pub(crate) fn push(&mut self, output: Range<usize>, location: &SourceLocation) {
+        if output.is_empty() {
+            return;
+        }
+        if let Some(last) = self.mappings.last_mut()
+            && last.output.end == output.start
+            && &last.location == location {
+            last.output.end = output.end;
+            return;
+        }
+        self.mappings.push(SourceMapping { output, location: location.clone() });
+    }
+
+    /// Appends the mappings of output that was itself appended at `offset`
+    pub(crate) fn extend_at(&mut self, other: SourceMap, offset: usize) {
+        for mapping in other.mappings {
+            let output = mapping.output.start + offset..mapping.output.end + offset;
+            self.push(output, &mapping.location);
+        }
+    }
+}
@@ -1,23 +1,47 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use std::sync::RwLock;
 
 use crate::Value as Obj;
-use crate::{tokenize, Token};
-use super::compile::compile;
+use crate::{tokenize_with, tokenize_with_locations, Delimiters, Token};
+use super::compile::{compile_with, Compiled, ErrorPolicy, RenderOptions};
+use super::context::Context;
+use super::i18n::Catalog;
+use super::cache::{FragmentCache, LruCache};
+use super::analysis::{self, TemplateAnalysis};
+use super::output::{self, OutputMode};
 
 /// Manages template loading, caching, and rendering
 pub struct TemplateManager {
-    /// Base directory for template files
-    template_dir: PathBuf,
+    /// Directories searched in order for template files
+    search_paths: Vec<PathBuf>,
+    /// Directories searched in order for `@namespace/...` templates
+    namespaces: HashMap<String, Vec<PathBuf>>,
     /// Cache of parsed templates
     template_cache: Arc<RwLock<HashMap<String, Vec<Token>>>>, 
+    /// Cache of parsed data files read by `load`, kept with the token cache 
+    data_cache: Arc<RwLock<HashMap<String, Obj>>>, 
     /// Maximum recursion depth for template inheritance 
     max_recursion_depth: u32, 
     /// Cache enabled flag
     cache_enabled: bool,
+    /// Markers that open and close a directive
+    delimiters: Delimiters,
+    /// Error policy, message catalog, escaping, minification and fragment cache used when rendering
+    options: RenderOptions,
+    /// Template sources compiled into the binary, looked up before the template directory
+    embedded: Arc<HashMap<String, String>>,
+    /// Wrap inserted templates in `<!-- begin ... -->` and `<!-- end ... -->` comments
+    boundary_comments: bool,
+    /// Tokenize with source locations, only set on the manager of a source-mapped render
+    locations: bool,
+    /// Output modes by lowercase file extension, used instead of `options.output_mode` 
+    /// for templates with one of these extensions 
+    extension_modes: HashMap<String, OutputMode>,
+    /// Pick the output mode of templates with a well-known extension by that extension 
+    detect_output_mode: bool,
 } 
 
 impl TemplateManager {
@@ -27,16 +51,56 @@ impl TemplateManager {
     /// * `template_dir` - Path to the directory containing template files 
     pub fn new<P: AsRef<Path>>(template_dir: P) -> Self {
         TemplateManager {
-            template_dir: template_dir.as_ref().to_path_buf(),
+            search_paths: vec![template_dir.as_ref().to_path_buf()],
+            namespaces: HashMap::new(),
             template_cache: Arc::new(RwLock::new(HashMap::new())), 
+            data_cache: Arc::new(RwLock::new(HashMap::new())), 
             max_recursion_depth: 10, 
             cache_enabled: true,
+            delimiters: Delimiters::default(),
+            options: RenderOptions {
+                fragment_cache: Some(Arc::new(LruCache::default())),
+                ..RenderOptions::default()
+            },
+            embedded: Arc::new(HashMap::new()),
+            boundary_comments: false,
+            locations: false,
+            extension_modes: HashMap::new(),
+            detect_output_mode: true,
         }
     } 
 
-    /// Get a full dir of a path 
+    /// Get a full dir of a path: the first search path, or namespace directory for `@namespace/...` 
+    /// paths, that has the file. If none has it, the path in the first directory. 
     pub fn get_template_path(&self, path: &str) -> PathBuf {
-        self.template_dir.join(path) 
+        let (directories, path) = match path.strip_prefix('@').and_then(|rest| rest.split_once('/')) {
+            Some((namespace, rest)) => match self.namespaces.get(namespace) {
+                Some(directories) => (directories.as_slice(), rest),
+                None => return PathBuf::from(path),
+            },
+            None => (self.search_paths.as_slice(), path),
+        };
+        directories.iter()
+            .map(|directory| directory.join(path))
+            .find(|candidate| candidate.is_file())
+            .or_else(|| directories.first().map(|directory| directory.join(path)))
+            .unwrap_or_else(|| PathBuf::from(path))
+    } 
+
+    /// Adds a directory searched after the ones given so far. Put a theme first and the 
+    /// directory it is based on after it, and the theme overrides individual files. 
+    /// Resolution applies to `template` and `insert` paths as well. 
+    pub fn with_search_path<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.search_paths.push(directory.as_ref().to_path_buf());
+        self
+    } 
+
+    /// Adds a directory for templates named `@namespace/...`, e.g. `"@admin/layout.html"`, 
+    /// so a plugin can ship its own templates. Directories added for the same namespace 
+    /// are searched in the order they were added. 
+    pub fn with_namespace<N: Into<String>, P: AsRef<Path>>(mut self, namespace: N, directory: P) -> Self {
+        self.namespaces.entry(namespace.into()).or_default().push(directory.as_ref().to_path_buf());
+        self
     } 
 
     /// Enables or disables template caching
@@ -51,6 +115,139 @@ impl TemplateManager {
         self
     } 
 
+    /// Use other directive markers than `-[` and `]-`, e.g. 
+    /// `with_delimiters(Delimiters::try_new("{{", "}}")?)`. 
+    /// They apply to every template this manager loads, including parents and inserted templates. 
+    pub fn with_delimiters(mut self, delimiters: Delimiters) -> Self {
+        self.delimiters = delimiters;
+        self
+    } 
+
+    /// Choose how recoverable template errors are reported: rendered inline as HTML comments 
+    /// (the default), failing the render, or passed to a callback. 
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.options.error_policy = policy;
+        self
+    } 
+
+    /// Escape values for the HTML context they are written in: text, attribute, URL,
+    /// script or style. Off by default. `raw(value)` writes a value unescaped.
+    pub fn with_auto_escape(mut self, enabled: bool) -> Self {
+        self.options.auto_escape = enabled;
+        self
+    } 
+
+    /// Minify the rendered HTML: collapse whitespace, drop it next to block-level tags and 
+    /// strip comments, leaving `<pre>`, `<textarea>`, `<script>` and `<style>` contents alone. 
+    /// Off by default. The output is minified as it is written, without a second buffer. 
+    /// Errors rendered inline by the error policy are comments too, so they are stripped. 
+    pub fn with_minify(mut self, enabled: bool) -> Self {
+        self.options.minify = enabled;
+        self
+    } 
+
+    /// Marks where each inserted template starts and ends in the output with 
+    /// `<!-- begin partials/nav.html -->` and `<!-- end partials/nav.html -->` comments, 
+    /// to tell which partial produced a part of a page. Off by default. 
+    pub fn with_boundary_comments(mut self, enabled: bool) -> Self {
+        self.boundary_comments = enabled;
+        self
+    } 
+
+    /// Debug mode: `-[ debug value ]-` writes the value as indented JSON, in a `<pre>`
+    /// element for HTML. Off by default, when `debug` directives write nothing.
+    /// `assert` directives are checked either way, and report through the error policy.
+    pub fn with_debug(mut self, enabled: bool) -> Self {
+        self.options.debug = enabled;
+        self
+    }
+
+    /// Sets the store for the output of `cache` blocks, replacing the in-memory
+    /// [`LruCache`] of 1024 fragments every manager starts with. Fragments are kept
+    /// apart from the token cache, so `with_caching(false)` does not affect them.
+    pub fn with_fragment_cache<C: FragmentCache + 'static>(mut self, cache: C) -> Self {
+        self.options.fragment_cache = Some(Arc::new(cache));
+        self
+    } 
+
+    /// Renders `cache` blocks every time instead of storing their output
+    pub fn without_fragment_cache(mut self) -> Self {
+        self.options.fragment_cache = None;
+        self
+    } 
+
+    /// The store for the output of `cache` blocks, e.g. to remove a fragment whose data changed
+    pub fn fragment_cache(&self) -> Option<&Arc<dyn FragmentCache>> {
+        self.options.fragment_cache.as_ref()
+    } 
+
+    /// The options templates are rendered with, for [`render_with`](Self::render_with) 
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    } 
+
+    /// Renders every template and string in `mode`, except templates with an extension 
+    /// registered with [`with_extension_mode`](Self::with_extension_mode). 
+    /// 
+    /// Without it, the mode follows the extension of the rendered template, see 
+    /// [`mode_for_extension`](output::mode_for_extension), and is `Html` for other extensions 
+    /// and for strings. Parents and inserted templates are rendered in the mode of the 
+    /// template they are part of. 
+    pub fn with_output_mode(mut self, mode: OutputMode) -> Self {
+        self.options.output_mode = mode;
+        self.detect_output_mode = false;
+        self
+    } 
+
+    /// Renders templates whose name ends in `.extension` in `mode`, e.g. a custom mode for 
+    /// `"yaml"`. Takes precedence over the built-in modes and over `with_output_mode`. 
+    pub fn with_extension_mode<E: AsRef<str>>(mut self, extension: E, mode: OutputMode) -> Self {
+        self.extension_modes.insert(extension.as_ref().to_ascii_lowercase(), mode);
+        self
+    } 
+
+    /// The output mode for a template: registered for its extension, else the built-in 
+    /// mode for it, else the manager's mode 
+    pub fn output_mode_for(&self, template_name: &str) -> OutputMode {
+        self.extension_mode(template_name).unwrap_or_else(|| self.options.output_mode.clone())
+    } 
+
+    /// The output mode registered for the extension of `template_name`, or the built-in one 
+    /// if the manager detects modes 
+    fn extension_mode(&self, template_name: &str) -> Option<OutputMode> {
+        let extension = Path::new(template_name).extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if let Some(mode) = self.extension_modes.get(&extension) {
+            return Some(mode.clone());
+        }
+        self.detect_output_mode.then(|| output::mode_for_extension(&extension)).flatten()
+    } 
+
+    /// Sets the message catalog used by `trans` and `_(...)`.
+    /// The locale of a render is taken from its `locale` variable.
+    pub fn with_catalog<C: Catalog + 'static>(mut self, catalog: C) -> Self {
+        self.options.catalog = Some(Arc::new(catalog));
+        self
+    } 
+
+    /// Serves the given templates from memory instead of the template directory, e.g. the
+    /// output of `include_templates!`. Names are resolved like file paths relative to the
+    /// template directory. Templates not listed are still read from disk.
+    pub fn with_embedded_templates<I, N, S>(mut self, templates: I) -> Self
+    where
+        I: IntoIterator<Item = (N, S)>,
+        N: Into<String>,
+        S: Into<String>,
+    {
+        let embedded = Arc::make_mut(&mut self.embedded);
+        for (name, source) in templates {
+            embedded.insert(name.into(), source.into());
+        }
+        self
+    } 
+
     /// Add the template's tokens to the cache. If it already exists, it will be replaced. 
     pub fn load_into_cache(&self, template_name: &str) { 
         if self.cache_enabled { 
@@ -84,11 +281,51 @@ impl TemplateManager {
         Ok(tokens) 
     } 
 
+    /// Reads and parses a JSON data file for `-[ load "data/nav.json" as nav ]-`, through the 
+    /// same lookup as templates: embedded sources, search paths and namespaces. An absolute 
+    /// path, or one that climbs out of the template directory with `..`, is refused. Parsed files are cached 
+    /// with the tokens when caching is enabled. 
+    pub fn load_data(&self, data_name: &str) -> Result<Obj, String> { 
+        if self.cache_enabled 
+            && let Some(value) = self.data_cache.read().unwrap().get(data_name) { 
+            return Ok(value.clone()); 
+        } 
+        // The part looked up in the template directories, which must stay inside them 
+        let relative = match data_name.strip_prefix('@').and_then(|rest| rest.split_once('/')) { 
+            Some((_, rest)) => rest, 
+            None => data_name, 
+        }; 
+        let mut depth: i32 = 0; 
+        for component in Path::new(relative).components() { 
+            match component { 
+                Component::Normal(_) => depth += 1, 
+                Component::CurDir => {}, 
+                Component::ParentDir => depth -= 1, 
+                // An absolute path would replace the template directory when joined to it 
+                Component::RootDir | Component::Prefix(_) => depth = -1, 
+            } 
+            if depth < 0 { 
+                return Err(format!("Failed to load data '{}': outside the template directory", data_name)); 
+            } 
+        } 
+        let source = self.read_template_content(data_name) 
+            .map_err(|_| format!("Failed to load data '{}': file not found", data_name))?; 
+        let value = Obj::from_json(&source) 
+            .map_err(|e| format!("Failed to load data '{}': {}", data_name, e))?; 
+        if self.cache_enabled { 
+            self.data_cache.write().unwrap().insert(data_name.to_string(), value.clone()); 
+        } 
+        Ok(value) 
+    } 
+
     /// Tokenizes the template file 
     pub fn read_template_token(&self, template_name: &str) -> Result<Vec<Token>, String> {
         match self.read_template_content(template_name) { 
             Ok(content) => { 
-                return Ok(tokenize(&content)) 
+                if self.locations { 
+                    return Ok(tokenize_with_locations(&content, &self.delimiters, template_name)) 
+                } 
+                return Ok(tokenize_with(&content, &self.delimiters)) 
             }, 
             Err(e) => Err(e), 
         }
@@ -96,6 +333,13 @@ impl TemplateManager {
 
     /// Get the string content of a template file 
     fn read_template_content(&self, template_name: &str) -> Result<String, String> {
+        if let Some(source) = self.embedded.get(template_name.trim_start_matches('/')) {
+            return Ok(source.clone());
+        }
+        if let Some((namespace, _)) = template_name.strip_prefix('@').and_then(|rest| rest.split_once('/'))
+            && !self.namespaces.contains_key(namespace) {
+            return Err(format!("Failed to read template '{}': unknown namespace '@{}'", template_name, namespace));
+        }
         fs::read_to_string(
             &self.get_template_path(template_name)
         ).map_err(
@@ -105,25 +349,171 @@ impl TemplateManager {
 
     /// Loads and renders a template by name
     pub fn render(&self, template_name: &str, data: &HashMap<String, Obj>) -> Result<String, String> {
-        // Get all template tokens needed (with inheritance resolution)
-        let tokens = self.expand_template(self.load_tokens(template_name)?, template_name, &mut 0); 
-        // Use your existing compile function to process these tokens
-        compile(tokens, data.clone()) 
+        self.render_with(template_name, &Context::from(data.clone()), &self.options).map(|compiled| compiled.output)
     } 
 
-    pub fn render_string(&self, template_str: String, data: &HashMap<String, Obj>) -> Result<String, String> { 
-        // Tokenize the string content 
-        let tokens = tokenize(&template_str); 
-        // Insert template content into the token stream 
+    /// Renders a template by name with `options` in place of the manager's own, returning 
+    /// everything the render produced. The output mode still follows the template's extension 
+    /// where one is registered or detected. The other `render*` methods are shortcuts for this one. 
+    /// 
+    /// Pass [`options`](Self::options) to render a [`Context`] with lazy values as configured. 
+    /// With [`RenderOptions::source_map`] set, templates are read again rather than taken 
+    /// from the token cache, so their tokens carry locations for [`Compiled::source_map`]. 
+    pub fn render_with(&self, template_name: &str, context: &Context, options: &RenderOptions) -> Result<Compiled, String> { 
+        let options = match self.extension_mode(template_name) { 
+            Some(output_mode) => RenderOptions { output_mode, ..options.clone() }, 
+            None => options.clone(), 
+        }; 
+        let tokens = if options.source_map { 
+            let located = TemplateManager { locations: true, cache_enabled: false, ..self.shared_view() }; 
+            located.expand_template(located.load_tokens(template_name)?, template_name, &mut 0) 
+        } else { 
+            self.expand_template(self.load_tokens(template_name)?, template_name, &mut 0) 
+        }; 
+        compile_with(tokens, context.clone(), &options) 
+    } 
+
+    /// Renders a template string with `options` in place of the manager's own, like 
+    /// [`render_with`](Self::render_with) 
+    pub fn render_string_with(&self, template_str: String, context: &Context, options: &RenderOptions) -> Result<Compiled, String> { 
+        // Tokenize the string content and insert template content into the token stream 
+        let tokens = if options.source_map { 
+            tokenize_with_locations(&template_str, &self.delimiters, "") 
+        } else { 
+            tokenize_with(&template_str, &self.delimiters) 
+        }; 
         let tokens = self.expand_template(tokens, "", &mut 0); 
-        
-        // Use your existing compile function to process these tokens
-        compile(tokens, data.clone()) 
+        compile_with(tokens, context.clone(), options) 
+    } 
+
+    /// Renders a template like [`render`](Self::render) and also returns its variables as the
+    /// template left them, e.g. a page title or asset list computed while rendering.
+    /// `export` templates render nothing but still run for their variables.
+    pub fn render_with_state(&self, template_name: &str, data: &HashMap<String, Obj>) -> Result<(String, HashMap<String, Obj>), String> {
+        let options = RenderOptions { run_exports: true, ..self.options.clone() };
+        let compiled = self.render_with(template_name, &Context::from(data.clone()), &options)?;
+        Ok((compiled.output, compiled.context.into_values()))
+    } 
+
+    pub fn render_string(&self, template_str: String, data: &HashMap<String, Obj>) -> Result<String, String> { 
+        self.render_string_with(template_str, &Context::from(data.clone()), &self.options).map(|compiled| compiled.output)
+    } 
+
+    /// Statically analyzes a template and everything it pulls in, without rendering it. 
+    /// 
+    /// Missing parents and partials are listed in the result rather than failing, 
+    /// only a template that cannot be loaded itself is an error. 
+    pub fn analyze(&self, template_name: &str) -> Result<TemplateAnalysis, String> { 
+        let mut result = TemplateAnalysis { name: template_name.to_string(), ..Default::default() }; 
+        let tokens = self.load_tokens(template_name)?; 
+
+        // Follow the parent chain from the template up to the root ancestor 
+        let mut chain = vec![(template_name.to_string(), tokens.clone())]; 
+        while let Some((current_name, current_tokens)) = chain.last() { 
+            let parent_name = match self.extract_parent_template_name(current_tokens) { 
+                Some(parent_name) => get_full_dir(&parent_name, current_name), 
+                None => break, 
+            }; 
+            if chain.len() > self.max_recursion_depth as usize || chain.iter().any(|(name, _)| name == &parent_name) { 
+                break; 
+            } 
+            match self.load_tokens(&parent_name) { 
+                Ok(parent_tokens) => { 
+                    result.parent_chain.push(parent_name.clone()); 
+                    chain.push((parent_name, parent_tokens)); 
+                }, 
+                Err(_) => { 
+                    result.missing_templates.insert(parent_name); 
+                    break; 
+                } 
+            } 
+        } 
+
+        // Blocks of the chain, root ancestor first so the last definition is the one rendered 
+        for (name, chain_tokens) in chain.iter().rev() { 
+            for block in analysis::named_sections(chain_tokens, &Token::BlockKeyword) { 
+                result.blocks.entry(block).or_default().push(name.clone()); 
+            } 
+        } 
+        result.overridden_blocks = result.blocks.iter() 
+            .filter(|(_, definitions)| definitions.len() > 1) 
+            .map(|(block, _)| block.clone()) 
+            .collect(); 
+
+        // Visit the chain, every partial and the partials' own parents 
+        let mut pending = chain; 
+        while let Some((name, template_tokens)) = pending.pop() { 
+            if result.free_variables.contains_key(&name) { 
+                continue; 
+            } 
+            result.free_variables.insert(name.clone(), analysis::free_variables(&template_tokens)); 
+            for data_file in analysis::referenced_templates(&template_tokens, &Token::LoadKeyword) { 
+                result.data_files.insert(get_full_dir(&data_file, &name)); 
+            } 
+
+            let inserts = analysis::referenced_templates(&template_tokens, &Token::InsertKeyword); 
+            let parents = analysis::referenced_templates(&template_tokens, &Token::TemplateKeyword); 
+            for (reference, is_insert) in inserts.into_iter().map(|r| (r, true)).chain(parents.into_iter().map(|r| (r, false))) { 
+                let reference = get_full_dir(&reference, &name); 
+                if is_insert { 
+                    result.inserts.insert(reference.clone()); 
+                } 
+                if result.free_variables.contains_key(&reference) || result.missing_templates.contains(&reference) { 
+                    continue; 
+                } 
+                match self.load_tokens(&reference) { 
+                    Ok(reference_tokens) => pending.push((reference, reference_tokens)), 
+                    Err(_) => { 
+                        result.missing_templates.insert(reference); 
+                    } 
+                } 
+            } 
+        } 
+
+        // What the compiler will actually see once inheritance and inserts are resolved 
+        let expanded = self.expand_template(tokens, template_name, &mut 0); 
+        let rendered_blocks = analysis::named_sections(&expanded, &Token::BlockKeyword); 
+        result.dead_blocks = result.blocks.keys() 
+            .filter(|block| !rendered_blocks.contains(*block)) 
+            .cloned() 
+            .collect(); 
+        result.unresolved_placeholders = analysis::named_sections(&expanded, &Token::PlaceholderKeyword) 
+            .difference(&rendered_blocks) 
+            .cloned() 
+            .collect(); 
+        result.required_variables = analysis::free_variables(&expanded); 
+
+        Ok(result) 
+    } 
+
+    /// A manager with the same settings that shares this one's token cache 
+    fn shared_view(&self) -> TemplateManager { 
+        TemplateManager { 
+            search_paths: self.search_paths.clone(), 
+            namespaces: self.namespaces.clone(), 
+            template_cache: self.template_cache.clone(), 
+            data_cache: self.data_cache.clone(), 
+            max_recursion_depth: self.max_recursion_depth, 
+            cache_enabled: self.cache_enabled, 
+            delimiters: self.delimiters.clone(), 
+            options: self.options.clone(), 
+            embedded: self.embedded.clone(), 
+            boundary_comments: self.boundary_comments, 
+            locations: self.locations, 
+            extension_modes: self.extension_modes.clone(), 
+            detect_output_mode: self.detect_output_mode, 
+        } 
+    } 
+
+    /// Reports a template error as an error token, which the compiler renders through the 
+    /// error policy and output mode like any error it finds itself 
+    fn report_error(&self, message: String) -> Vec<Token> { 
+        vec![Token::Error(message)] 
     } 
 
     pub fn expand_template(&self, tokens: Vec<Token>, self_dir: &str, recursion_count: &mut u32) -> Vec<Token> { 
         if *recursion_count > self.max_recursion_depth { 
-            return vec![Token::HtmlContent(format!("<!-- Template Error: Maximum recursion depth exceeded -->"))]; 
+            return self.report_error("Template Error: Maximum recursion depth exceeded".to_string()); 
         } 
 
         *recursion_count += 1; // Increment recursion count 
@@ -134,13 +524,24 @@ impl TemplateManager {
         // Extend with parent template if applicable
         match self.extend_with_parent(tokens, self_dir, recursion_count) { 
             Ok(tokens) => tokens, 
-            Err(e) => vec![Token::HtmlContent(format!("<!-- Template Error: {} -->", e))], 
+            Err(e) => self.report_error(format!("Template Error: {}", e)), 
         } 
     } 
 
     pub fn insert_template(&self, mut tokens: Vec<Token>, self_dir: &str, recursion_count: &mut u32) -> Vec<Token> { 
         let mut i = 0; 
         while i < tokens.len() { 
+            if matches!(tokens.get(i), Some(Token::LoadKeyword)) { 
+                // Resolve the data here, where the path is known, into `let name = value` 
+                let end = tokens[i..].iter() 
+                    .position(|token| matches!(token, Token::EndOfStatement)) 
+                    .map_or(tokens.len(), |offset| i + offset + 1); 
+                let resolved = self.resolve_load(&tokens[i..end], self_dir); 
+                let resolved_len = resolved.len(); 
+                tokens.splice(i..end, resolved); 
+                i += resolved_len; 
+                continue; 
+            } 
             if matches!(tokens.get(i), Some(Token::InsertKeyword)) && 
                i + 1 < tokens.len() {
                 
@@ -163,13 +564,17 @@ impl TemplateManager {
                     // Only proceed if we found the end statement
                     if found_end {
                         // Load template tokens without unnecessary clone
-                        let tokens_to_insert = match self.load_tokens(&full_path) {
+                        let mut tokens_to_insert = match self.load_tokens(&full_path) {
                             Ok(template_tokens) => self.expand_template(template_tokens, &full_path, recursion_count),
                             Err(e) => {
                                 // More informative error token
-                                vec![Token::HtmlContent(format!("<!-- Template Error: {} - {} -->", full_path, e))]
+                                self.report_error(format!("Template Error: {} - {}", full_path, e))
                             }
                         };
+                        if self.boundary_comments {
+                            tokens_to_insert.insert(0, Token::HtmlContent(format!("<!-- begin {} -->", full_path)));
+                            tokens_to_insert.push(Token::HtmlContent(format!("<!-- end {} -->", full_path)));
+                        }
                         
                         // Calculate new position before modifying vector
                         let new_position = j + 1 + tokens_to_insert.len();
@@ -189,6 +594,26 @@ impl TemplateManager {
         tokens // Return the modified tokens vector
     } 
 
+    /// Turns a `load "path" as name` directive into `let name = value`, or into an error token
+    fn resolve_load(&self, directive: &[Token], self_dir: &str) -> Vec<Token> { 
+        match directive { 
+            [Token::LoadKeyword, Token::Object(Obj::Str(path)), Token::Identifier(as_word), Token::Identifier(name), Token::EndOfStatement] 
+                if as_word == "as" => { 
+                match self.load_data(&get_full_dir(path, self_dir)) { 
+                    Ok(value) => vec![ 
+                        Token::LetKeyword, 
+                        Token::Identifier(name.clone()), 
+                        Token::Assignment, 
+                        Token::Object(value), 
+                        Token::EndOfStatement, 
+                    ], 
+                    Err(e) => self.report_error(format!("Template Error: {}", e)), 
+                } 
+            }, 
+            _ => self.report_error("Template Error: expected `load \"path\" as name`".to_string()), 
+        } 
+    } 
+
     pub fn extend_with_parent(&self, tokens: Vec<Token>, self_dir: &str, recursion_count: &mut u32) -> Result<Vec<Token>, String> { 
         // Check if this template extends another one
         if let Some(parent_name) = self.extract_parent_template_name(&tokens) {
@@ -370,10 +795,30 @@ impl Default for TemplateManager {
     }
 } 
 
-/// To see whether the dir is a relative dir or a absolute dir 
-/// If absolute dir, return the path 
-/// If relative dir, return the path with the ori path 
+/// Resolves `path`, as written in an `insert`, `template` or `load` directive of the
+/// template named `ori`, to a name relative to the template directory.
+/// 
+/// - A path starting with `/` or `\` is relative to the template directory. 
+/// - `@namespace/...` paths are returned as they are. 
+/// - Other paths are relative to the directory of `ori`, so a relative path inside a 
+///   namespaced template stays in that namespace. A template at the top of the template 
+///   directory has no directory of its own, so its paths name templates in the template directory. 
+/// 
+/// This is the name [`TemplateManager::get_template_path`] and [`TemplateManager::load_data`] 
+/// expect, e.g. for tools that follow the references of a template. 
+/// 
+/// ```rust
+/// use akari::get_full_dir;
+/// assert_eq!(get_full_dir("base.html", "page.html"), "base.html");
+/// assert_eq!(get_full_dir("item.html", "partials/nav.html"), "partials/item.html");
+/// assert_eq!(get_full_dir("/base.html", "partials/nav.html"), "base.html");
+/// assert_eq!(get_full_dir("@admin/layout.html", "page.html"), "@admin/layout.html");
+/// ```
 pub fn get_full_dir(path: &str, ori: &str) -> String { 
+    if path.starts_with('@') { 
+        // A namespaced path names its namespace itself 
+        return path.to_string() 
+    } 
     if path.starts_with("/") || path.starts_with("\\") { 
         // Return path without the first character 
         let path = path.trim_start_matches("/").trim_start_matches("\\").to_string();
@@ -385,7 +830,8 @@ pub fn get_full_dir(path: &str, ori: &str) -> String {
         } else if let Some(pos) = ori.rfind('\\') {
             &ori[..pos]
         } else {
-            ori
+            // A top-level template, so the path is relative to the template directory 
+            return path.to_string() 
         }; 
         // Handle relative path
         // First check if the original path ends with a separator
@@ -61,7 +61,6 @@ macro_rules! parse_string {
 #[cfg(test)] 
 mod tests {
     use super::*; 
-    use crate::object; 
 
     #[test]
     fn test_tokenize() {
@@ -81,12 +80,11 @@ mod tests {
             -[ endfor ]-
         -[ endblock ]- 
         "#; 
+        let tokens = parse::tokenize(input); 
+        assert_eq!(tokens.iter().filter(|token| matches!(token, parse::Token::BlockKeyword)).count(), 2); 
+        assert!(tokens.contains(&parse::Token::Object(Value::new("template.html")))); 
         let mut data = HashMap::new(); 
-        data.insert("list".to_string(), object!(vec![        object!("a"), 
-        object!("b"), 
-        object!("c")])); 
+        data.insert("list".to_string(), Value::new(vec!["a", "b", "c"])); 
         // println!("{:?}", render_string(input, data)); 
     } 
 } 
- 
- 
\ No newline at end of file
@@ -242,4 +242,926 @@ fn test3() -> Result<(), Box<dyn std::error::Error>>{
         println!("Rendered Template:\n{}", result);
         
         Ok(()) 
-} 
\ No newline at end of file
+} 
+#[test] 
+fn test_range_expressions() -> Result<(), Box<dyn std::error::Error>> { 
+    let data = HashMap::new(); 
+    
+    let render = |template: &str| super::render(template, &data); 
+    assert_eq!(render("-[ for i in 0..4 ]--[ i ]- -[ endfor ]-")?, "0 1 2 3 "); 
+    assert_eq!(render("-[ for i in 1..=3 ]--[ i ]- -[ endfor ]-")?, "1 2 3 "); 
+    assert_eq!(render("-[ for i in 2..10 step 2 ]--[ i ]- -[ endfor ]-")?, "2 4 6 8 "); 
+    assert_eq!(render("-[ for i in 10..=0 step 5 ]--[ i ]- -[ endfor ]-")?, "10 5 0 "); 
+    assert_eq!(render("-[ for i in 3..0 ]--[ i ]- -[ endfor ]-")?, "3 2 1 "); 
+    assert_eq!(render("-[ let step = 3 ]--[ for i in 0..=step ]--[ i ]--[ endfor ]-")?, "0123"); 
+    assert_eq!(render("-[ let step = 2 ]--[ let xs = 0..3 ]--[ step ]--[ for i in xs ]--[ i ]--[ endfor ]-")?, "2012"); 
+    assert_eq!(render("-[ for i in 1.5..3 ]--[ i ]- -[ endfor ]-")?, "1.5 2.5 "); 
+    assert!(render("-[ for i in 0..3 step 0 ]--[ i ]--[ endfor ]-").is_err()); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_membership_operator() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    data.insert("roles".to_string(), Obj::List(vec![Obj::Str("admin".to_string()), Obj::Str("editor".to_string())])); 
+    let mut user = HashMap::new(); 
+    user.insert("name".to_string(), Obj::Str("akari".to_string())); 
+    data.insert("user".to_string(), Obj::Dict(user)); 
+    data.insert("page".to_string(), Obj::Numerical(3.0)); 
+    
+    let render = |template: &str| super::render(template, &data); 
+    assert_eq!(render(r#"-[ output "admin" in roles ]-"#)?, "true"); 
+    assert_eq!(render(r#"-[ output "guest" in roles ]-"#)?, "false"); 
+    assert_eq!(render(r#"-[ output "guest" not in roles ]-"#)?, "true"); 
+    assert_eq!(render(r#"-[ output "name" in user ]-"#)?, "true"); 
+    assert_eq!(render(r#"-[ output "email" not in user ]-"#)?, "true"); 
+    assert_eq!(render(r#"-[ output "kar" in user.name ]-"#)?, "true"); 
+    assert_eq!(render("-[ output page in 1..=3 ]-")?, "true"); 
+    assert_eq!(render("-[ if page + 1 in 1..=3 ]-next-[ endif ]-")?, ""); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_conditional_expressions() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    data.insert("active".to_string(), Obj::Boolean(true)); 
+    data.insert("count".to_string(), Obj::Numerical(0.0)); 
+    data.insert("nothing".to_string(), Obj::None); 
+    let mut user = HashMap::new(); 
+    user.insert("name".to_string(), Obj::Str("akari".to_string())); 
+    data.insert("user".to_string(), Obj::Dict(user)); 
+    
+    let render = |template: &str| super::render(template, &data); 
+    
+    // Ternary, only the selected branch is evaluated 
+    assert_eq!(render(r#"<li class="-[ output active ? "on" : "off" ]-">"#)?, r#"<li class="on">"#); 
+    assert_eq!(render(r#"-[ output count ? "some" : "none" ]-"#)?, "none"); 
+    assert_eq!(render(r#"-[ output count > 1 ? "many" : count == 1 ? "one" : "zero" ]-"#)?, "zero"); 
+    assert_eq!(render(r#"-[ output nothing ? nothing.name : "guest" ]-"#)?, "guest"); 
+    assert_eq!(render(r#"-[ output active ? user.name : nothing.name ]-"#)?, "akari"); 
+    assert_eq!(render(r#"-[ let label = active ? "yes" : "no" ]--[ label ]-"#)?, "yes"); 
+    
+    // Null coalescing covers undefined variables, None and missing keys 
+    assert_eq!(render(r#"-[ output title ?? "Untitled" ]-"#)?, "Untitled"); 
+    assert_eq!(render(r#"-[ output nothing ?? "fallback" ]-"#)?, "fallback"); 
+    assert_eq!(render(r#"-[ output user.email ?? "no email" ]-"#)?, "no email"); 
+    assert_eq!(render(r#"-[ output user["email"] ?? user.name ]-"#)?, "akari"); 
+    assert_eq!(render(r#"-[ output user.name ?? missing.key ]-"#)?, "akari"); 
+    assert_eq!(render(r#"-[ output count ?? 5 ]-"#)?, "0"); 
+    // A failed lookup falls back wherever `??` appears: in a bare directive or after parentheses 
+    assert_eq!(render(r#"-[ title ?? "Untitled" ]-"#)?, "Untitled"); 
+    assert_eq!(render(r#"-[ user.email ?? "no email" ]-|-[ user.name ?? "nobody" ]-"#)?, "no email|akari"); 
+    assert_eq!(render(r#"-[ output (user.email) ?? "no email" ]-"#)?, "no email"); 
+    assert_eq!(render(r#"-[ if (user.email ?? false) ]-yes-[ endif ]-"#)?, ""); 
+    assert!(render("-[ output user.email ]-").is_err()); 
+    
+    // Optional chaining 
+    assert_eq!(render("-[ output user?.name ]-")?, "akari"); 
+    assert_eq!(render("-[ output nothing?.name.first ]-")?, ""); 
+    assert_eq!(render(r#"-[ output user?.email ?? "none" ]-"#)?, "none"); 
+    assert_eq!(render(r#"-[ output (nothing?.name ?? "anonymous").len ]-"#)?, "9"); 
+    // Only a None receiver short-circuits, other lookups fail like `.` 
+    assert!(render("-[ output user?.email ]-").is_err()); 
+    assert!(render("-[ output count?.name ]-").is_err()); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_truthiness() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    let values = vec![ 
+        ("yes", Obj::Boolean(true), true), 
+        ("no", Obj::Boolean(false), false), 
+        ("zero", Obj::Numerical(0.0), false), 
+        ("one", Obj::Numerical(1.0), true), 
+        ("negative", Obj::Numerical(-2.5), true), 
+        ("empty_str", Obj::Str(String::new()), false), 
+        ("str", Obj::Str("0".to_string()), true), 
+        ("empty_list", Obj::List(vec![]), false), 
+        ("list", Obj::List(vec![Obj::None]), true), 
+        ("empty_dict", Obj::Dict(HashMap::new()), false), 
+        ("nothing", Obj::None, false), 
+    ]; 
+    for (name, value, _) in &values { 
+        data.insert(name.to_string(), value.clone()); 
+    } 
+    
+    // `if`, `!`, `not`, `&&`, `||` and `while` must all agree 
+    for (name, _, truthy) in &values { 
+        let expected = truthy.to_string(); 
+        let negated = (!truthy).to_string(); 
+        assert_eq!(super::render(&format!("-[ if {} ]-true-[ endif ]-", name), &data)?, if *truthy { "true" } else { "" }, "if {}", name); 
+        assert_eq!(super::render(&format!("-[ output !{} ]-", name), &data)?, negated, "!{}", name); 
+        assert_eq!(super::render(&format!("-[ output not {} ]-", name), &data)?, negated, "not {}", name); 
+        assert_eq!(super::render(&format!("-[ output {} && true ]-", name), &data)?, expected, "{} && true", name); 
+        assert_eq!(super::render(&format!("-[ output {} and true ]-", name), &data)?, expected, "{} and true", name); 
+        assert_eq!(super::render(&format!("-[ output false || {} ]-", name), &data)?, expected, "false || {}", name); 
+        assert_eq!(super::render(&format!("-[ output false or {} ]-", name), &data)?, expected, "false or {}", name); 
+        assert_eq!(super::render(&format!("-[ let n = {} ]--[ while n ]-x-[ n = false ]--[ endwhile ]-", name), &data)?, if *truthy { "x" } else { "" }, "while {}", name); 
+    } 
+    
+    // Word operators combine like their symbolic forms and short-circuit without evaluating 
+    assert_eq!(super::render("-[ output one and not zero or no ]-", &data)?, "true"); 
+    assert_eq!(super::render("-[ output nothing and nothing.missing ]-", &data)?, "false"); 
+    assert_eq!(super::render("-[ output one || nothing.missing ]-after", &data)?, "trueafter"); 
+    
+    // `not` negates a whole comparison, while `!` binds to its operand 
+    data.insert("l".to_string(), Obj::new(vec![2])); 
+    data.insert("x".to_string(), Obj::new(2)); 
+    assert_eq!(super::render("-[ output not 1 in l ]-|-[ output not x == 1 ]-|-[ output !x == 1 ]-", &data)?, "true|true|false"); 
+    assert_eq!(super::render("-[ output not x == 1 and x == 2 ]-", &data)?, "true"); 
+    assert!(super::render("-[ output 1 !in l ]-", &data).is_err()); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_string_interpolation() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    let mut user = HashMap::new(); 
+    user.insert("name".to_string(), Obj::Str("akari".to_string())); 
+    user.insert("id".to_string(), Obj::Numerical(42.0)); 
+    data.insert("user".to_string(), Obj::Dict(user)); 
+    data.insert("count".to_string(), Obj::Numerical(3.0)); 
+    
+    let render = |template: &str| super::render(template, &data); 
+    assert_eq!( 
+        render(r#"-[ output "Hello ${user.name}, you have ${count} messages" ]-"#)?, 
+        "Hello akari, you have 3 messages" 
+    ); 
+    assert_eq!(render(r#"<a href="-[ output "/users/${user.id}/posts?page=${count + 1}" ]-">"#)?, r#"<a href="/users/42/posts?page=4">"#); 
+    assert_eq!(render(r#"-[ let title = "${user.name} (${count > 1 ? "many" : "one"})" ]--[ title ]-"#)?, "akari (many)"); 
+    assert_eq!(render(r#"-[ output "${missing ?? "nobody"}!" ]-"#)?, "nobody!"); 
+    assert_eq!(render(r#"-[ output "${user.name}".len ]-"#)?, "5"); 
+    assert_eq!(render(r#"-[ output "cost: \${count}" ]-"#)?, "cost: ${count}"); 
+    assert_eq!(render(r#"-[ output "$5 and {braces}" ]-"#)?, "$5 and {braces}"); 
+    assert!(render(r#"-[ output "${}" ]-"#).is_err()); 
+    assert!(render(r#"-[ output "${count count}" ]-"#).is_err()); 
+    
+    // An unclosed `${` is a lexer error and stops at the end of the directive 
+    let tokens = crate::tokenize(r#"-[ let title = "Hi ${user.name ]-<h1>"#); 
+    assert!(matches!(tokens[3], crate::Token::Error(_)), "{:?}", tokens); 
+    assert_eq!(tokens.last(), Some(&crate::Token::HtmlContent("<h1>".to_string()))); 
+    assert!(render(r#"-[ output "Hi ${user.name" ]-"#).is_err()); 
+    assert!(render(r#"-[ output "Hi ${user.name"#).is_err()); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_verbatim_and_escaped_markers() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    data.insert("name".to_string(), Obj::Str("akari".to_string())); 
+    
+    let render = |template: &str| super::render(template, &data); 
+    assert_eq!( 
+        render("-[ verbatim ]-<p>-[ output name ]-</p>-[endverbatim]- -[ name ]-")?, 
+        "<p>-[ output name ]-</p> akari" 
+    ); 
+    assert_eq!(render("-[verbatim]-]- and -[ -[ endverbatim ]-")?, "]- and -[ "); 
+    assert_eq!(render("-[ verbatim ]-no end -[ name ]-")?, "no end -[ name ]-"); 
+    assert_eq!(render(r"Write \-[ name ]- to print -[ name ]-")?, "Write -[ name ]- to print akari"); 
+    assert_eq!(render(r"\]- and ]- are both literal")?, "]- and ]- are both literal"); 
+    
+    // Inside a string literal the markers are plain text, escaped or not 
+    assert_eq!(render(r#"-[ output "a ]- b -[ c" ]-"#)?, "a ]- b -[ c"); 
+    assert_eq!(render(r#"-[ output "\]- \-[" ]-"#)?, "]- -["); 
+    let braces = crate::Delimiters::try_new("{{", "}}")?; 
+    let tokens = crate::tokenize_with(r#"\}} {{ output "}}" }}"#, &braces); 
+    assert_eq!(crate::compile(tokens, data.clone())?, "}} }}"); 
+    assert_eq!(render("-[ let verbatim_count = 1 ]--[ verbatim_count ]-")?, "1"); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_custom_delimiters() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::Delimiters; 
+    
+    let mut data = HashMap::new(); 
+    data.insert("name".to_string(), Obj::Str("akari".to_string())); 
+    data.insert("items".to_string(), Obj::List(vec![Obj::Numerical(1.0), Obj::Numerical(2.0)])); 
+    
+    // Mustache-style markers, where `-[` is ordinary text 
+    let mustache = TemplateManager::new("").with_delimiters(Delimiters::try_new("{{", "}}")?); 
+    assert_eq!( 
+        mustache.render_string("-[ {{ name }} ]- {{ for i in items }}{{ output i % 2 }}{{ endfor }}".to_string(), &data)?, 
+        "-[ akari ]- 10" 
+    ); 
+    assert_eq!( 
+        mustache.render_string(r#"{{ verbatim }}{{ name }}{{ endverbatim }} \{{ name }}"#.to_string(), &data)?, 
+        "{{ name }} {{ name }}" 
+    ); 
+    
+    // Markdown tables are left alone with markers that never appear in them 
+    let markdown = TemplateManager::new("").with_delimiters(Delimiters::try_new("<#", "#>")?); 
+    assert_eq!( 
+        markdown.render_string("| -[x]- | <# output name.len #> |".to_string(), &data)?, 
+        "| -[x]- | 5 |" 
+    ); 
+    
+    // Per-call configuration without a manager 
+    let tokens = crate::tokenize_with("@( name )@", &Delimiters::try_new("@(", ")@")?); 
+    assert_eq!(tokens, vec![crate::Token::Identifier("name".to_string()), crate::Token::EndOfStatement]); 
+    
+    // Markers that would be misread are refused 
+    assert!(Delimiters::try_new("", "]-").is_err()); 
+    assert!(Delimiters::try_new("$$", "$$").is_err()); 
+    assert!(Delimiters::try_new("{ {", "} }").is_err()); 
+    for close in ["%}", ">>", "]", "]]", ")", "==", "end", "1]"] { 
+        assert!(Delimiters::try_new("<#", close).is_err(), "{}", close); 
+    } 
+    assert_eq!(Delimiters::try_new("-[", "]-")?, Delimiters::default()); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_error_policy() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::ErrorPolicy; 
+    use std::sync::{Arc, Mutex}; 
+    
+    let mut data = HashMap::new(); 
+    data.insert("list".to_string(), Obj::List(vec![Obj::Numerical(1.0)])); 
+    let broken = [ 
+        "-[ missing ]-", 
+        "-[ placeholder nav ]-", 
+        "-[ insert \"/does_not_exist.html\" ]-", 
+        "-[ template \"/does_not_exist.html\" ]-", 
+        "-[ for x in true ]--[ endfor ]-", 
+        "-[ del list[5] ]-", 
+    ]; 
+    
+    // Inline keeps the previous behaviour of rendering HTML comments 
+    let inline = TemplateManager::new("./test_temp/error_policy"); 
+    assert_eq!(inline.render_string("a-[ missing ]-b".to_string(), &data)?, "a<!-- There should be a value 'missing' but not found -->b"); 
+    assert_eq!(inline.render_string("-[ placeholder nav ]-".to_string(), &data)?, "<!-- Block 'nav' not defined -->"); 
+    
+    // Errors found while expanding inserts and parents render the way the output mode renders errors 
+    let text = TemplateManager::new("./test_temp/error_policy").with_output_mode(crate::OutputMode::Text); 
+    let rendered = text.render_string("a-[ insert \"/does_not_exist.html\" ]-b".to_string(), &data)?; 
+    assert!(rendered.starts_with("a[Error: Template Error: does_not_exist.html - ") && rendered.ends_with("]b"), "{}", rendered); 
+    
+    // Fail turns every one of them into an error 
+    let fail = TemplateManager::new("./test_temp/error_policy").with_error_policy(ErrorPolicy::Fail); 
+    for template in broken { 
+        assert!(fail.render_string(template.to_string(), &data).is_err(), "{}", template); 
+    } 
+    assert_eq!(fail.render_string("-[ for x in list ]--[ x ]--[ endfor ]-".to_string(), &data)?, "1"); 
+    
+    // Callback collects the errors and renders nothing in their place 
+    let errors = Arc::new(Mutex::new(Vec::new())); 
+    let collected = errors.clone(); 
+    let callback = TemplateManager::new("./test_temp/error_policy") 
+        .with_error_policy(ErrorPolicy::callback(move |message| collected.lock().unwrap().push(message.to_string()))); 
+    for template in broken { 
+        // Without its parent a child template has nothing to render 
+        let expected = if template.contains("template ") { "" } else { "ab" }; 
+        assert_eq!(callback.render_string(format!("a{}b", template), &data)?, expected, "{}", template); 
+    } 
+    assert_eq!(errors.lock().unwrap().len(), broken.len()); 
+    assert_eq!(errors.lock().unwrap()[0], "There should be a value 'missing' but not found"); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_analyze() -> Result<(), Box<dyn std::error::Error>> { 
+    use std::collections::BTreeSet; 
+    
+    let template_dir = Path::new("./test_temp/templates_analyze"); 
+    fs::create_dir_all(template_dir.join("partials"))?; 
+    fs::write(template_dir.join("base.html"), r#"<title>-[ title ]-</title> 
+-[ insert "partials/nav.html" ]- 
+-[ block content ]-default-[ endblock ]- 
+-[ block footer ]--[ output year ?? 2025 ]--[ endblock ]- 
+-[ placeholder sidebar ]-"#)?; 
+    fs::write(template_dir.join("partials/nav.html"), r#"-[ for link in links ]--[ output link.href ]--[ endfor ]--[ insert "/gone.html" ]-"#)?; 
+    fs::write(template_dir.join("page.html"), r#"-[ template "base.html" ]- 
+-[ block content ]--[ let greeting = "Hello ${user.name}" ]--[ greeting ]--[ for i in 0..count step 2 ]--[ i ]--[ endfor ]--[ endblock ]- 
+-[ block unused ]-never rendered-[ endblock ]-"#)?; 
+    
+    let template_manager = TemplateManager::new(template_dir); 
+    let analysis = template_manager.analyze("page.html")?; 
+    let set = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<BTreeSet<String>>(); 
+    
+    assert_eq!(analysis.parent_chain, vec!["base.html".to_string()]); 
+    assert_eq!(analysis.inserts, set(&["partials/nav.html", "gone.html"])); 
+    assert_eq!(analysis.missing_templates, set(&["gone.html"])); 
+    assert_eq!(analysis.blocks["content"], vec!["base.html".to_string(), "page.html".to_string()]); 
+    assert_eq!(analysis.overridden_blocks, set(&["content"])); 
+    assert_eq!(analysis.dead_blocks, set(&["unused"])); 
+    assert_eq!(analysis.unresolved_placeholders, set(&["sidebar"])); 
+    assert_eq!(analysis.free_variables["page.html"], set(&["count", "user"])); 
+    assert_eq!(analysis.free_variables["partials/nav.html"], set(&["links"])); 
+    assert_eq!(analysis.required_variables, set(&["count", "links", "title", "user", "year"])); 
+    assert_eq!(analysis.dependencies(), set(&["base.html", "gone.html", "page.html", "partials/nav.html"])); 
+    
+    assert!(template_manager.analyze("does_not_exist.html").is_err()); 
+    
+    // Reads are checked in order, against the bindings made so far 
+    let free = |source: &str| super::analysis::free_variables(&crate::tokenize(source)); 
+    assert_eq!(free("-[ x ]--[ let x = 1 ]--[ x ]-"), set(&["x"])); 
+    assert_eq!(free("-[ let x = 1 ]--[ x ]-"), set(&[])); 
+    assert_eq!(free("-[ let total = total + 1 ]-"), set(&["total"])); 
+    assert_eq!(free("-[ for item in items ]--[ seen ]--[ let seen = item ]--[ endfor ]-"), set(&["items", "seen"])); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_relative_template_paths() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::template::template_manager::get_full_dir; 
+    
+    assert_eq!(get_full_dir("base.html", "page.html"), "base.html"); 
+    assert_eq!(get_full_dir("item.html", "partials/nav.html"), "partials/item.html"); 
+    assert_eq!(get_full_dir("/base.html", "partials/nav.html"), "base.html"); 
+    
+    // Top-level templates name their parent and partials from the template directory, 
+    // partials name their own partials from the directory they are in 
+    let template_dir = Path::new("./test_temp/templates_relative"); 
+    fs::create_dir_all(template_dir.join("partials"))?; 
+    fs::write(template_dir.join("base.html"), "<nav>-[ insert \"partials/nav.html\" ]-</nav>-[ block body ]--[ endblock ]-")?; 
+    fs::write(template_dir.join("partials/nav.html"), "-[ insert \"item.html\" ]-")?; 
+    fs::write(template_dir.join("partials/item.html"), "home")?; 
+    fs::write(template_dir.join("page.html"), "-[ template \"base.html\" ]--[ block body ]-page-[ endblock ]-")?; 
+    
+    let template_manager = TemplateManager::new(template_dir); 
+    assert_eq!(template_manager.render("page.html", &HashMap::new())?, "<nav>home</nav>page"); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+#[cfg(feature = "template_macro")] 
+fn test_embedded_templates() -> Result<(), Box<dyn std::error::Error>> { 
+    const TEMPLATES: &[(&str, &str)] = crate::include_templates!("tests/templates"); 
+    
+    let names: Vec<&str> = TEMPLATES.iter().map(|(name, _)| *name).collect(); 
+    assert_eq!(names, vec!["base.html", "page.html", "partials/greeting.html"]); 
+    assert_eq!(crate::template!("tests/templates/base.html"), TEMPLATES[0].1); 
+    
+    // The directory does not exist, so everything has to come from memory 
+    let template_manager = TemplateManager::new("./does_not_exist").with_embedded_templates(TEMPLATES.iter().copied()); 
+    let mut data = HashMap::new(); 
+    data.insert("name".to_string(), Obj::new("Akari")); 
+    assert_eq!(template_manager.render("page.html", &data)?.trim(), "<html>Hello Akari!</html>"); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_directive_words_as_variables() -> Result<(), Box<dyn std::error::Error>> { 
+    // Directive words added after 0.2 are only keywords at the start of a directive, 
+    // so reading a variable with such a name alone needs `output` 
+    let mut data = HashMap::new(); 
+    data.insert("load".to_string(), Obj::new(1)); 
+    data.insert("debug".to_string(), Obj::new(true)); 
+    let render = |template: &str| super::render(template, &data); 
+    assert_eq!(render("-[ let trans = 2 ]--[ let cache = load + trans ]--[ output cache ]-|-[ output trans ]-")?, "3|2"); 
+    assert_eq!(render("-[ if debug ]--[ output \"${load}\" ]--[ endif ]-")?, "1"); 
+    assert_eq!(crate::tokenize("-[ cache ]-")[0], crate::Token::CacheKeyword); 
+    assert_eq!(crate::tokenize("-[ output cache ]-")[1], crate::Token::Identifier("cache".to_string())); 
+    
+    Ok(()) 
+} 
+
+#[test] 
+fn test_i18n() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::{MessageCatalog, plural_category, translatable_keys, tokenize}; 
+    
+    let template_dir = Path::new("./test_temp/templates_i18n"); 
+    fs::create_dir_all(template_dir)?; 
+    fs::write(template_dir.join("catalog.json"), r#"{ 
+        "en": { 
+            "nav": { "home": "Home" }, 
+            "greeting": "Hello, {name}!", 
+            "items": { "zero": "No items", "one": "{count} item", "other": "{count} items" }, 
+            "files": { "one": "{0} file in {1}", "other": "{0} files in {1}" } 
+        }, 
+        "ru": { 
+            "items": { "one": "{count} предмет", "few": "{count} предмета", "many": "{count} предметов", "other": "{count} предмета" } 
+        } 
+    }"#)?; 
+    fs::write(template_dir.join("page.html"), r#"-[ trans "nav.home" ]-|-[ trans "greeting", name = user ]-|-[ _("items", count = n) ]-|-[ output _("files", 3, "docs") ]-|-[ trans "untranslated {name}", name = user ]-"#)?; 
+    
+    let catalog = MessageCatalog::from_jsonf(template_dir.join("catalog.json").to_string_lossy(), "en")?; 
+    let template_manager = TemplateManager::new(template_dir).with_catalog(catalog); 
+    
+    let mut data = HashMap::new(); 
+    data.insert("user".to_string(), Obj::new("Ann")); 
+    data.insert("n".to_string(), Obj::new(1)); 
+    assert_eq!(template_manager.render("page.html", &data)?, "Home|Hello, Ann!|1 item|3 files in docs|untranslated Ann"); 
+    
+    // Regional locales fall back to their language, then to the default locale 
+    data.insert("locale".to_string(), Obj::new("ru-RU")); 
+    for (n, expected) in [(1, "1 предмет"), (3, "3 предмета"), (11, "11 предметов"), (21, "21 предмет"), (0, "0 предметов")] { 
+        data.insert("n".to_string(), Obj::new(n)); 
+        assert_eq!(template_manager.render_string(r#"-[ _("items", count = n) ]-"#.to_string(), &data)?, expected); 
+    } 
+    assert_eq!(template_manager.render_string(r#"-[ trans "nav.home" ]-"#.to_string(), &data)?, "Home"); 
+    
+    // An explicit zero form wins over the language rule 
+    data.insert("locale".to_string(), Obj::new("en")); 
+    assert_eq!(template_manager.render_string(r#"-[ _("items", count = n) ]-"#.to_string(), &data)?, "No items"); 
+    
+    assert_eq!(plural_category("fr", 0.0), "one"); 
+    assert_eq!(plural_category("ar", 2.0), "two"); 
+    assert_eq!(plural_category("ja", 1.0), "other"); 
+    
+    let keys: Vec<String> = translatable_keys(&tokenize(r#"-[ trans "a" ]- -[ let x = "${_("b")}" ]- -[ _(name) ]-"#)).into_iter().collect(); 
+    assert_eq!(keys, vec!["a".to_string(), "b".to_string()]); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_escaping() -> Result<(), Box<dyn std::error::Error>> { 
+    let mut data = HashMap::new(); 
+    data.insert("text".to_string(), Obj::new("<b>\"Tom\" & 'Jerry'</b>")); 
+    data.insert("url".to_string(), Obj::new("javascript:alert(1)")); 
+    data.insert("query".to_string(), Obj::new("a b&c")); 
+    
+    // Explicit escape functions work with or without auto-escaping 
+    let template_manager = TemplateManager::new(""); 
+    let render = |template: &str| template_manager.render_string(template.to_string(), &data); 
+    assert_eq!(render("-[ text ]-")?, "<b>\"Tom\" & 'Jerry'</b>"); 
+    assert_eq!(render("-[ escape_html(text) ]-")?, "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"); 
+    assert_eq!(render(r#"-[ escape_attr("a b=") ]-"#)?, "a&#x20;b&#x3D;"); 
+    assert_eq!(render("-[ escape_url(url) ]-|-[ escape_url(\"/a b?x=1\") ]-")?, "#|/a%20b?x=1"); 
+    assert_eq!(render("-[ urlencode(query) ]-")?, "a%20b%26c"); 
+    assert_eq!(render(r#"-[ escape_js("</script>'") ]-"#)?, "\\x3C\\x2Fscript\\x3E\\x27"); 
+    assert_eq!(render(r#"-[ escape_css("a;}") ]-"#)?, "a\\3B \\7D "); 
+    
+    // Auto-escaping picks the escaper from where the value lands 
+    let template_manager = TemplateManager::new("").with_auto_escape(true); 
+    let render = |template: &str| template_manager.render_string(template.to_string(), &data); 
+    assert_eq!(render("<p>-[ text ]-</p>")?, "<p>&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;</p>"); 
+    assert_eq!(render(r#"<a href="-[ url ]-">"#)?, r##"<a href="#">"##); 
+    assert_eq!(render(r#"<a href="/search?q=-[ query ]-" title=-[ query ]->"#)?, r#"<a href="/search?q=a%20b%26c" title=a&#x20;b&#x26;c>"#); 
+    assert_eq!(render(r#"<button onclick="go('-[ query ]-')">"#)?, r#"<button onclick="go('a b\x26c')">"#); 
+    assert_eq!(render("<script>var q = \"-[ output query ]-\";</script><i>-[ query ]-</i>")?, "<script>var q = \"a b\\x26c\";</script><i>a b&amp;c</i>"); 
+    assert_eq!(render("<style>p { content: \"-[ query ]-\" }</style>")?, "<style>p { content: \"a\\20 b\\26 c\" }</style>"); 
+    assert_eq!(render("-[ for i in 0..1 ]-<a href=\"-[ endfor ]--[ url ]-\">")?, "<a href=\"#\">"); 
+    assert_eq!(render("<p>-[ raw(text) ]-</p>")?, "<p><b>\"Tom\" & 'Jerry'</b></p>"); 
+    assert_eq!(render("<p>-[ output raw(text) + \"&\" ]-</p>")?, "<p>&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;&amp;</p>"); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_render_with_state() -> Result<(), Box<dyn std::error::Error>> { 
+    let template_dir = Path::new("./test_temp/templates_state"); 
+    fs::create_dir_all(template_dir)?; 
+    fs::write(template_dir.join("base.html"), r#"<title>-[ block title ]--[ endblock ]-</title>-[ block body ]--[ endblock ]-"#)?; 
+    fs::write(template_dir.join("page.html"), r#"-[ template "base.html" ]- 
+-[ block title ]--[ let page_title = "Home" ]--[ page_title ]--[ endblock ]- 
+-[ block body ]--[ if chart ]--[ assets = assets + chart_assets ]--[ endif ]--[ del chart ]-body-[ endblock ]-"#)?; 
+    fs::write(template_dir.join("meta.html"), r#"-[ export ]--[ let description = "Generated by " + name ]-"#)?; 
+    
+    let template_manager = TemplateManager::new(template_dir); 
+    let mut data = HashMap::new(); 
+    data.insert("chart".to_string(), Obj::new(true)); 
+    data.insert("name".to_string(), Obj::new("akari")); 
+    data.insert("assets".to_string(), Obj::new(vec!["app.js"])); 
+    data.insert("chart_assets".to_string(), Obj::new(vec!["chart.js"])); 
+    
+    let (output, state) = template_manager.render_with_state("page.html", &data)?; 
+    assert_eq!(output, "<title>Home</title>body"); 
+    assert_eq!(state["page_title"], Obj::new("Home")); 
+    assert_eq!(state["assets"], Obj::new(vec!["app.js", "chart.js"])); 
+    assert!(!state.contains_key("chart")); 
+    assert_eq!(state["name"], Obj::new("akari")); 
+    
+    // An export template renders nothing but still computes its values 
+    assert_eq!(template_manager.render("meta.html", &data)?, ""); 
+    let (output, state) = template_manager.render_with_state("meta.html", &data)?; 
+    assert_eq!(output, ""); 
+    assert_eq!(state["description"], Obj::new("Generated by akari")); 
+    
+    // render_with takes every per-render setting at once 
+    let options = crate::RenderOptions { run_exports: true, source_map: true, ..Default::default() }; 
+    let compiled = template_manager.render_with("page.html", &crate::Context::from(data.clone()), &options)?; 
+    assert_eq!(compiled.output, "<title>Home</title>body"); 
+    assert_eq!(compiled.context.get("page_title"), Some(&Obj::new("Home"))); 
+    assert_eq!(compiled.source_map.location_at(0).map(|location| location.template.as_str()), Some("base.html")); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_lazy_context() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::Context; 
+    use std::sync::Arc; 
+    use std::sync::atomic::{AtomicUsize, Ordering}; 
+    
+    let calls = Arc::new(AtomicUsize::new(0)); 
+    let counter = calls.clone(); 
+    let mut context = Context::new(); 
+    context.insert("show", false); 
+    context.insert_lazy("sidebar", move || { 
+        counter.fetch_add(1, Ordering::SeqCst); 
+        Obj::new("links") 
+    }); 
+    
+    let template_manager = TemplateManager::new(""); 
+    let template = "-[ if show ]--[ sidebar ]--[ sidebar.len ]--[ endif ]-".to_string(); 
+    assert_eq!(template_manager.render_string_with(template.clone(), &context, template_manager.options())?.output, ""); 
+    assert_eq!(calls.load(Ordering::SeqCst), 0); 
+    
+    // Computed once, then memoized for every later read and render 
+    context.insert("show", true); 
+    assert_eq!(template_manager.render_string_with(template.clone(), &context, template_manager.options())?.output, "links5"); 
+    assert_eq!(template_manager.render_string_with(template, &context, template_manager.options())?.output, "links5"); 
+    assert_eq!(calls.load(Ordering::SeqCst), 1); 
+    assert!(context.is_evaluated("sidebar")); 
+    
+    // Assignment and deletion behave as for plain values 
+    context.insert_lazy("title", || Obj::new("lazy")); 
+    assert_eq!(template_manager.render_string_with("-[ title = \"set\" ]--[ title ]-".to_string(), &context, template_manager.options())?.output, "set"); 
+    assert_eq!(template_manager.render_string_with("-[ del title ]--[ output title ?? \"gone\" ]-".to_string(), &context, template_manager.options())?.output, "gone"); 
+    assert!(!context.is_evaluated("title")); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_search_paths_and_namespaces() -> Result<(), Box<dyn std::error::Error>> { 
+    let root = Path::new("./test_temp/templates_search"); 
+    fs::create_dir_all(root.join("base/partials"))?; 
+    fs::create_dir_all(root.join("theme/partials"))?; 
+    fs::create_dir_all(root.join("admin"))?; 
+    fs::write(root.join("base/layout.html"), r#"[-[ block body ]--[ endblock ]-]-[ insert "partials/footer.html" ]-"#)?; 
+    fs::write(root.join("base/partials/footer.html"), "base footer")?; 
+    fs::write(root.join("theme/partials/footer.html"), "theme footer")?; 
+    fs::write(root.join("base/page.html"), r#"-[ template "layout.html" ]--[ block body ]-page-[ endblock ]-"#)?; 
+    fs::write(root.join("admin/layout.html"), r#"-[ template "/layout.html" ]--[ block body ]-admin:-[ insert "menu.html" ]--[ endblock ]-"#)?; 
+    fs::write(root.join("admin/menu.html"), "menu")?; 
+    fs::write(root.join("base/dashboard.html"), r#"-[ insert "@admin/layout.html" ]-"#)?; 
+    
+    let data = HashMap::new(); 
+    let base = TemplateManager::new(root.join("base")); 
+    assert_eq!(base.render("page.html", &data)?, "[page]base footer"); 
+    
+    // The theme overrides the footer only, everything else comes from the base 
+    let themed = TemplateManager::new(root.join("theme")) 
+        .with_search_path(root.join("base")) 
+        .with_namespace("admin", root.join("admin")); 
+    assert_eq!(themed.render("page.html", &data)?, "[page]theme footer"); 
+    
+    // Relative paths stay in the namespace, a leading slash leaves it 
+    assert_eq!(themed.render("@admin/layout.html", &data)?, "[admin:menu]theme footer"); 
+    assert_eq!(themed.render("dashboard.html", &data)?, "[admin:menu]theme footer"); 
+    assert!(themed.render("@missing/layout.html", &data).unwrap_err().contains("unknown namespace '@missing'")); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_minify() -> Result<(), Box<dyn std::error::Error>> { 
+    let template = r##" 
+<!DOCTYPE html> 
+<html> 
+    <head> 
+        <title> -[ title ]- </title> 
+        <style> p  { margin: 0 } </style> 
+    </head> 
+    <body> 
+        <!-- navigation --> 
+        <div   class="nav  bar"   > 
+            -[ for item in items ]- 
+            <a href="#">-[ item ]-</a> 
+            -[ endfor ]- 
+        </div> 
+        <p>Hello,   <b>world</b> !</p> 
+        <pre> 
+  keep   this 
+</pre> 
+        <script> if (a  <  b) { x = "  y  "; } </script> 
+    </body> 
+</html> 
+"##; 
+    let mut data = HashMap::new(); 
+    data.insert("title".to_string(), Obj::new("Home")); 
+    data.insert("items".to_string(), Obj::new(vec!["a", "b"])); 
+    
+    let template_manager = TemplateManager::new("").with_minify(true); 
+    assert_eq!( 
+        template_manager.render_string(template.to_string(), &data)?, 
+        concat!( 
+            r#"<!DOCTYPE html><html><head><title>Home</title><style> p  { margin: 0 } </style></head><body>"#, 
+            r##"<div class="nav  bar"><a href="#">a</a> <a href="#">b</a></div>"##, 
+            "<p>Hello, <b>world</b> !</p><pre> \n  keep   this \n</pre>", 
+            r#"<script> if (a  <  b) { x = "  y  "; } </script></body></html>"#, 
+        ) 
+    ); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_fragment_cache() -> Result<(), Box<dyn std::error::Error>> { 
+    use std::sync::Arc; 
+    use std::sync::atomic::{AtomicUsize, Ordering}; 
+    use crate::{Context, FragmentCache, LruCache}; 
+    
+    let template = r#"<nav>-[ cache "nav:" + user.role ttl 60 ]--[ let x = 1 ]--[ menu ]--[ endcache ]-</nav>-[ x ]-"#; 
+    let renders = Arc::new(AtomicUsize::new(0)); 
+    let context = |role: &str| { 
+        let mut context = Context::new(); 
+        context.insert("x", 0); 
+        context.insert("user", Obj::from_json(&format!(r#"{{"role": "{}"}}"#, role)).unwrap()); 
+        let renders = renders.clone(); 
+        let role = role.to_string(); 
+        context.insert_lazy("menu", move || { 
+            renders.fetch_add(1, Ordering::SeqCst); 
+            Obj::new(format!("menu for {}", role)) 
+        }); 
+        context 
+    }; 
+    
+    let template_manager = TemplateManager::new(""); 
+    for _ in 0..2 { 
+        assert_eq!( 
+            template_manager.render_string_with(template.to_string(), &context("admin"), template_manager.options())?.output, 
+            "<nav>menu for admin</nav>0" 
+        ); 
+    } 
+    assert_eq!(renders.load(Ordering::SeqCst), 1); 
+    assert_eq!( 
+        template_manager.render_string_with(template.to_string(), &context("guest"), template_manager.options())?.output, 
+        "<nav>menu for guest</nav>0" 
+    ); 
+    assert_eq!(renders.load(Ordering::SeqCst), 2); 
+    
+    // Invalidating a fragment renders it again 
+    template_manager.fragment_cache().unwrap().remove("nav:admin"); 
+    template_manager.render_string_with(template.to_string(), &context("admin"), template_manager.options())?; 
+    assert_eq!(renders.load(Ordering::SeqCst), 3); 
+    
+    // Without a store, the body runs every time 
+    let uncached = TemplateManager::new("").without_fragment_cache(); 
+    assert!(uncached.fragment_cache().is_none()); 
+    uncached.render_string_with(template.to_string(), &context("admin"), uncached.options())?; 
+    uncached.render_string_with(template.to_string(), &context("admin"), uncached.options())?; 
+    assert_eq!(renders.load(Ordering::SeqCst), 5); 
+    
+    // Fragments are kept per locale, so translated text is not served in another language 
+    let catalog = crate::MessageCatalog::new("en") 
+        .with_messages("en", Obj::from_json(r#"{ "home": "Home" }"#)?) 
+        .with_messages("fr", Obj::from_json(r#"{ "home": "Accueil" }"#)?); 
+    let localized = TemplateManager::new("").with_catalog(catalog); 
+    let nav = r#"-[ cache "nav" ]--[ trans "home" ]--[ endcache ]-"#; 
+    let mut data = HashMap::new(); 
+    for (locale, expected) in [("en", "Home"), ("fr", "Accueil"), ("en", "Home")] { 
+        data.insert("locale".to_string(), Obj::new(locale)); 
+        assert_eq!(localized.render_string(nav.to_string(), &data)?, expected); 
+    } 
+    assert!(localized.fragment_cache().unwrap().get("fr\0nav").is_some()); 
+    
+    // A key from the template cannot pose as a localized one 
+    data.insert("locale".to_string(), Obj::new("fr")); 
+    data.insert("key".to_string(), Obj::new("fr\0nav")); 
+    let posing = localized.render_string("-[ cache key ]-posing-[ endcache ]-".to_string(), &data)?; 
+    assert!(posing.ends_with("posing") && posing.contains("NUL"), "{}", posing); 
+    assert_eq!(localized.fragment_cache().unwrap().get("fr\0nav"), Some("Accueil".to_string())); 
+    
+    // A body that reported an error is not stored 
+    let broken = r#"-[ cache "broken" ]--[ missing ]--[ endcache ]-"#; 
+    let rendered = localized.render_string(broken.to_string(), &HashMap::new())?; 
+    assert!(rendered.contains("missing"), "{}", rendered); 
+    assert!(localized.fragment_cache().unwrap().get("broken").is_none()); 
+    
+    // The least recently used fragment is evicted first, and expired ones are never returned 
+    let cache = LruCache::new(2); 
+    cache.set("a", "1".to_string(), None); 
+    cache.set("b", "2".to_string(), None); 
+    assert_eq!(cache.get("a"), Some("1".to_string())); 
+    cache.set("c", "3".to_string(), None); 
+    assert_eq!(cache.get("b"), None); 
+    assert_eq!(cache.get("a"), Some("1".to_string())); 
+    cache.set("d", "4".to_string(), Some(std::time::Duration::ZERO)); 
+    assert_eq!(cache.get("d"), None); 
+    assert_eq!(cache.len(), 1); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_source_map() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::{Context, RenderOptions, SourceLocation}; 
+    
+    let root = Path::new("./test_temp/templates_source_map"); 
+    fs::create_dir_all(root.join("partials"))?; 
+    fs::write(root.join("layout.html"), "<html>\n-[ block body ]--[ endblock ]-\n-[ insert \"partials/footer.html\" ]-</html>")?; 
+    fs::write(root.join("partials/footer.html"), "<footer>\n  -[ year ]-\n</footer>\n")?; 
+    fs::write(root.join("page.html"), "-[ template \"layout.html\" ]-\n-[ block body ]-\n<main>\n-[ for i in 2 ]-<p>-[ i ]-</p>-[ endfor ]-\n</main>\n-[ endblock ]-")?; 
+    
+    let mut data = HashMap::new(); 
+    data.insert("year".to_string(), Obj::new(2024)); 
+    let template_manager = TemplateManager::new(root); 
+    let options = RenderOptions { source_map: true, ..template_manager.options().clone() }; 
+    let compiled = template_manager.render_with("page.html", &Context::from(data.clone()), &options)?; 
+    let (output, source_map) = (compiled.output, compiled.source_map); 
+    assert_eq!(output, template_manager.render("page.html", &data)?); 
+    assert_eq!(output, "<html>\n\n<main>\n<p>0</p><p>1</p>\n</main>\n\n<footer>\n  2024\n</footer>\n</html>"); 
+    
+    let at = |template: &str, line: usize, column: usize| SourceLocation { template: template.to_string(), line, column }; 
+    assert_eq!(source_map.location_of_line(&output, 1), Some(&at("layout.html", 1, 1))); 
+    assert_eq!(source_map.location_of_line(&output, 3), Some(&at("page.html", 3, 1))); 
+    assert_eq!(source_map.location_at(output.find("<p>1").unwrap()), Some(&at("page.html", 4, 17))); 
+    assert_eq!(source_map.location_at(output.find('1').unwrap()), Some(&at("page.html", 4, 20))); 
+    assert_eq!(source_map.location_at(output.find("2024").unwrap()), Some(&at("partials/footer.html", 2, 3))); 
+    assert_eq!(source_map.location_of_line(&output, 9), Some(&at("partials/footer.html", 3, 1))); 
+    assert_eq!(source_map.location_at(output.len()), None); 
+    
+    // Partial boundaries as comments 
+    let annotated = TemplateManager::new(root).with_boundary_comments(true); 
+    assert!(annotated.render("page.html", &data)?.ends_with( 
+        "\n<!-- begin partials/footer.html --><footer>\n  2024\n</footer>\n<!-- end partials/footer.html --></html>" 
+    )); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_output_modes() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::{OutputFormat, OutputMode}; 
+    
+    struct Yaml; 
+    impl OutputFormat for Yaml { 
+        fn format_error(&self, message: &str) -> String { 
+            format!("# error: {}", message) 
+        } 
+        fn escape(&self, value: &str) -> String { 
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")) 
+        } 
+    } 
+    
+    let root = Path::new("./test_temp/templates_output"); 
+    fs::create_dir_all(root)?; 
+    let source = "Hi -[ name ]-,  -[ missing ]-\n"; 
+    for file in ["mail.txt", "page.html", "notes.md", "config.yaml", "other.tpl"] { 
+        fs::write(root.join(file), source)?; 
+    } 
+    let mut data = HashMap::new(); 
+    data.insert("name".to_string(), Obj::new("<Tom & \"Jerry\">")); 
+    
+    let template_manager = TemplateManager::new(root).with_auto_escape(true).with_minify(true); 
+    let missing = "There should be a value 'missing' but not found"; 
+    assert_eq!( 
+        template_manager.render("mail.txt", &data)?, 
+        format!("Hi <Tom & \"Jerry\">,  [Error: {}]\n", missing) 
+    ); 
+    assert_eq!(template_manager.render("notes.md", &data)?, template_manager.render("mail.txt", &data)?); 
+    assert_eq!(template_manager.render("page.html", &data)?, "Hi &lt;Tom &amp; &quot;Jerry&quot;&gt;,"); 
+    assert_eq!(template_manager.render("other.tpl", &data)?, template_manager.render("page.html", &data)?); 
+    
+    // A custom mode for an extension, and a fixed mode for everything else 
+    let template_manager = TemplateManager::new(root) 
+        .with_auto_escape(true) 
+        .with_output_mode(OutputMode::Text) 
+        .with_extension_mode("YAML", OutputMode::custom(Yaml)); 
+    assert_eq!( 
+        template_manager.render("config.yaml", &data)?, 
+        format!("Hi \"<Tom & \\\"Jerry\\\">\",  # error: {}\n", missing) 
+    ); 
+    assert_eq!(template_manager.render("page.html", &data)?, template_manager.render("mail.txt", &data)?); 
+    assert_eq!(template_manager.render_string("-[ name ]-".to_string(), &data)?, "<Tom & \"Jerry\">"); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_format_template() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::{format_template, tokenize, FormatOptions}; 
+    
+    let source = r#"<ul>
+  -[for item in items]-
+-[if item.price>10&&!item.hidden]-
+      <li class="-[item . kind]-">-[ output -item.price ]- \-[x]- -[output _( "key",count=n )]--[ names[ 0 ] ]--[i++]-</li>
+        -[ endif ]-
+-[endfor]-
+</ul>
+-[ verbatim ]--[a+b]--[ endverbatim ]--[let x=-1]-"#; 
+    let formatted = format_template(source, &FormatOptions::default())?; 
+    assert_eq!(formatted, r#"<ul>
+  -[ for item in items ]-
+-[ if item.price > 10 && !item.hidden ]-
+      <li class="-[ item.kind ]-">-[ output -item.price ]- \-[x]- -[ output _("key", count = n) ]--[ names[0] ]--[ i++ ]-</li>
+        -[ endif ]-
+-[ endfor ]-
+</ul>
+-[ verbatim ]--[a+b]--[ endverbatim ]--[ let x = -1 ]-"#); 
+    assert_eq!(tokenize(formatted.as_str()), tokenize(source)); 
+    assert_eq!(format_template(&formatted, &FormatOptions::default())?, formatted); 
+    
+    // Indenting only touches lines holding nothing but directives 
+    let options = FormatOptions { indent: Some("  ".to_string()), ..FormatOptions::default() }; 
+    assert_eq!(format_template(source, &options)?, r#"<ul>
+-[ for item in items ]-
+  -[ if item.price > 10 && !item.hidden ]-
+      <li class="-[ item.kind ]-">-[ output -item.price ]- \-[x]- -[ output _("key", count = n) ]--[ names[0] ]--[ i++ ]-</li>
+  -[ endif ]-
+-[ endfor ]-
+</ul>
+-[ verbatim ]--[a+b]--[ endverbatim ]--[ let x = -1 ]-"#); 
+    
+    // An unclosed directive is kept as it is 
+    assert_eq!(format_template("a -[if  x", &FormatOptions::default())?, "a -[if  x"); 
+    
+    // Regions without tokens of their own are kept too 
+    let source = "-[verbatim]--[ endverbatim ]-\\]- -[x]--[ ]--[ verbatim ]--[endverbatim]-"; 
+    assert_eq!(format_template(source, &FormatOptions::default())?, "-[verbatim]--[ endverbatim ]-\\]- -[ x ]--[ ]--[ verbatim ]--[endverbatim]-"); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_assert_and_debug() -> Result<(), Box<dyn std::error::Error>> { 
+    use crate::ErrorPolicy; 
+    use std::sync::{Arc, Mutex}; 
+    
+    let mut data = HashMap::new(); 
+    data.insert("user".to_string(), Obj::from_json(r#"{"name": "<Ann>", "age": 15, "roles": ["admin"]}"#)?); 
+    let source = "-[ assert user.age >= 18, \"${user.name} is ${user.age}\" ]-ok-[ assert user ]--[ debug user ]-"; 
+    
+    // Inline by default, nothing written for a holding assertion or a debug outside debug mode 
+    let template_manager = TemplateManager::new(""); 
+    assert_eq!( 
+        template_manager.render_string(source.to_string(), &data)?, 
+        "<!-- Assertion failed: <Ann> is 15 -->ok" 
+    ); 
+    assert_eq!(template_manager.render_string("-[ assert missing ]-".to_string(), &data)?, "<!-- Assertion failed -->"); 
+    
+    // Failing the render, or collected as diagnostics 
+    let strict = TemplateManager::new("").with_error_policy(ErrorPolicy::Fail); 
+    assert_eq!( 
+        strict.render_string(source.to_string(), &data), 
+        Err("Assertion failed: <Ann> is 15".to_string()) 
+    ); 
+    let failures = Arc::new(Mutex::new(Vec::new())); 
+    let collected = failures.clone(); 
+    let collecting = TemplateManager::new("") 
+        .with_error_policy(ErrorPolicy::callback(move |message| collected.lock().unwrap().push(message.to_string()))); 
+    assert_eq!(collecting.render_string(source.to_string(), &data)?, "ok"); 
+    assert_eq!(*failures.lock().unwrap(), vec!["Assertion failed: <Ann> is 15".to_string()]); 
+    
+    // Debug mode dumps the value as indented JSON 
+    let debugging = TemplateManager::new("").with_debug(true); 
+    assert_eq!( 
+        debugging.render_string("-[ debug user ]-".to_string(), &data)?, 
+        "<pre class=\"akari-debug\">{\n  &quot;age&quot;: 15,\n  &quot;name&quot;: &quot;&lt;Ann&gt;&quot;,\n  &quot;roles&quot;: [\n    &quot;admin&quot;\n  ]\n}</pre>" 
+    ); 
+    let debugging = debugging.with_output_mode(crate::OutputMode::Text); 
+    assert_eq!(debugging.render_string("-[ debug user.roles ]- -[ debug 3 ]-".to_string(), &data)?, "[\n  \"admin\"\n] 3"); 
+    
+    Ok(()) 
+}
+
+#[test] 
+fn test_load_data() -> Result<(), Box<dyn std::error::Error>> { 
+    let root = Path::new("./test_temp/templates_load"); 
+    fs::create_dir_all(root.join("data"))?; 
+    fs::create_dir_all(root.join("pages"))?; 
+    fs::write(root.join("data/nav.json"), r#"[{"title": "Home", "url": "/"}, {"title": "Blog", "url": "/blog"}]"#)?; 
+    fs::write(root.join("data/broken.json"), "{ not json")?; 
+    fs::write(root.join("nav.html"), r#"-[ load "data/nav.json" as nav ]--[ for link in nav ]-<a href="-[ link.url ]-">-[ link.title ]-</a>-[ endfor ]-"#)?; 
+    fs::write(root.join("pages/about.html"), r#"-[ load "../data/nav.json" as links ]--[ links[1].title ]-|-[ insert "../nav.html" ]-"#)?; 
+    
+    let template_manager = TemplateManager::new(root); 
+    let nav = r#"<a href="/">Home</a><a href="/blog">Blog</a>"#; 
+    assert_eq!(template_manager.render("nav.html", &HashMap::new())?, nav); 
+    assert_eq!(template_manager.render("pages/about.html", &HashMap::new())?, format!("Blog|{}", nav)); 
+    
+    // Parsed once and cached with the tokens 
+    fs::write(root.join("data/nav.json"), "[]")?; 
+    assert_eq!(template_manager.render("nav.html", &HashMap::new())?, nav); 
+    assert_eq!(TemplateManager::new(root).render("nav.html", &HashMap::new())?, ""); 
+    
+    // Missing, invalid and out-of-tree files go through the error policy 
+    let render = |source: &str| template_manager.render_string(source.to_string(), &HashMap::new()); 
+    assert_eq!(render(r#"-[ load "data/none.json" as x ]-"#)?, "<!-- Template Error: Failed to load data 'data/none.json': file not found -->"); 
+    assert!(render(r#"-[ load "data/broken.json" as x ]-"#)?.starts_with("<!-- Template Error: Failed to load data 'data/broken.json': ")); 
+    assert_eq!( 
+        render(r#"-[ load "../templates_load/data/nav.json" as x ]-"#)?, 
+        "<!-- Template Error: Failed to load data '../templates_load/data/nav.json': outside the template directory -->" 
+    ); 
+    assert_eq!(render(r#"-[ load "data/nav.json" ]-"#)?, "<!-- Template Error: expected `load \"path\" as name` -->"); 
+    
+    // Absolute paths are refused too, since joining one replaces the template directory 
+    for absolute in ["/etc/passwd", "../../etc/passwd", "@data//etc/passwd"] { 
+        let error = template_manager.load_data(absolute).unwrap_err(); 
+        assert!(error.ends_with("outside the template directory"), "{}", error); 
+    } 
+    
+    // The loaded name is bound, not required from the caller 
+    let analysis = template_manager.analyze("pages/about.html")?; 
+    assert!(analysis.required_variables.is_empty(), "{:?}", analysis.required_variables); 
+    assert!(analysis.dependencies().contains("pages/../data/nav.json"), "{:?}", analysis.data_files); 
+    
+    Ok(()) 
+}
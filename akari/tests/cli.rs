@@ -0,0 +1,143 @@
+#![cfg(feature = "bin")]
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `akari` with the given arguments and stdin
+fn akari(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_akari"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start akari");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_data_files() {
+    let dir = "./test_temp/cli_data";
+    fs::create_dir_all(dir).unwrap();
+    fs::write(format!("{}/site.json", dir), r#"{"site": {"name": "Akari"}, "title": "Site", "n": 1}"#).unwrap();
+    fs::write(format!("{}/page.json", dir), r#"{"title": "Page", "n": 2}"#).unwrap();
+    fs::write(format!("{}/list.json", dir), "[1, 2]").unwrap();
+    let template = "-[ site.name ]-|-[ title ]-|-[ n ]-|-[ from ]-";
+
+    // Later files override earlier ones, and key=value pairs override every file
+    let output = akari(&[
+        "render_string", template,
+        "n=3", "--data", &format!("{}/site.json", dir), "--data", "-", "--data", &format!("{}/page.json", dir),
+    ], r#"{"from": "stdin", "title": "Stdin"}"#);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Akari|Page|3|stdin");
+
+    let output = akari(&["render_string", template, "--data", &format!("{}/list.json", dir)], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be a JSON object"));
+
+    let output = akari(&["render_string", template, "--data", &format!("{}/none.json", dir)], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to read data"));
+}
+
+#[test]
+fn test_output_and_exit_codes() {
+    let dir = "./test_temp/cli_output";
+    fs::create_dir_all(dir).unwrap();
+    let templates = format!("{}/tests/templates", env!("CARGO_MANIFEST_DIR"));
+
+    // The output is exactly the rendered text, on stdout or in a file
+    let output = akari(&["--template-dir", &templates, "render", "page.html", "name=\"Bo\""], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "<html>Hello Bo!</html>\n");
+    let file = format!("{}/page.html", dir);
+    let output = akari(&["render", "page.html", "-o", &file, "--template-dir", &templates], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "<html></html>\n");
+
+    // Usage errors, missing templates and render errors
+    assert_eq!(akari(&["render"], "").status.code(), Some(2));
+    assert_eq!(akari(&["bogus", "x"], "").status.code(), Some(2));
+    assert_eq!(akari(&["render_string", "x", "--output"], "").status.code(), Some(2));
+    assert_eq!(akari(&["--template-dir", &templates, "render", "none.html"], "").status.code(), Some(3));
+    let output = akari(&["--template-dir", &templates, "render", "page.html", "--data", "-"], "[");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Template errors are reported on stderr and fail the command, after the output is written
+    fs::write(format!("{}/broken.html", dir), "<p>-[ insert \"partials/missing.html\" ]-</p>\n").unwrap();
+    let output = akari(&["--template-dir", dir, "render", "broken.html"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "<p></p>\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("partials/missing.html"));
+    let output = akari(&["render_string", "a-[ assert 1 == 2, \"bad\" ]-b"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ab");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad"));
+
+    // Help and version list the commands
+    for flag in ["--help", "--version"] {
+        let output = akari(&[flag], "");
+        assert_eq!(output.status.code(), Some(0));
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        for command in ["render", "render_string", "extract", "fmt", "build"] {
+            assert!(stdout.contains(&format!("\n  {} ", command)), "{} misses {}", flag, command);
+        }
+    }
+}
+
+#[test]
+fn test_build() {
+    let src = "./test_temp/cli_build/site";
+    let out = "./test_temp/cli_build/out";
+    let _ = fs::remove_dir_all("./test_temp/cli_build");
+    fs::create_dir_all(format!("{}/_layouts", src)).unwrap();
+    fs::create_dir_all(format!("{}/_partials", src)).unwrap();
+    fs::create_dir_all(format!("{}/blog", src)).unwrap();
+    fs::write(format!("{}/_layouts/base.html", src), "<title>-[ site ]- | -[ title ]-</title>-[ block body ]--[ endblock ]-").unwrap();
+    fs::write(format!("{}/_partials/nav.html", src), "<nav>-[ site ]-</nav>").unwrap();
+    fs::write(format!("{}/index.html", src), "-[ template \"_layouts/base.html\" ]--[ block body ]--[ insert \"_partials/nav.html\" ]-Home-[ endblock ]-").unwrap();
+    fs::write(format!("{}/blog/post.html", src), "---\n{\"title\": \"Post\"}\n---\n-[ template \"/_layouts/base.html\" ]--[ block body ]-Post body-[ endblock ]-").unwrap();
+    fs::write(format!("{}/footer.html", src), "---\n{\"partial\": true}\n---\n<footer></footer>").unwrap();
+    fs::write(format!("{}/style.css", src), "body { margin: 0 }").unwrap();
+    fs::write(format!("{}/.draft.html", src), "draft").unwrap();
+    fs::write("./test_temp/cli_build/site.json", r#"{"site": "Akari", "title": "Home"}"#).unwrap();
+
+    let output = akari(&["build", src, out, "--data", "./test_temp/cli_build/site.json"], "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read_to_string(format!("{}/index.html", out)).unwrap(), "<title>Akari | Home</title><nav>Akari</nav>Home");
+    // Front matter overrides the shared data
+    assert_eq!(fs::read_to_string(format!("{}/blog/post.html", out)).unwrap(), "<title>Akari | Post</title>Post body");
+    assert_eq!(fs::read_to_string(format!("{}/style.css", out)).unwrap(), "body { margin: 0 }");
+    for skipped in ["_layouts", "_partials", "footer.html", ".draft.html"] {
+        assert!(!std::path::Path::new(&format!("{}/{}", out, skipped)).exists(), "{}", skipped);
+    }
+    let written = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(written.lines().count(), 3, "{}", written);
+
+    // Pages with errors are still written, but the build fails
+    fs::write(format!("{}/broken.html", src), "-[ insert \"_partials/missing.html\" ]-").unwrap();
+    let output = akari(&["build", src, out], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("broken.html"));
+    assert!(std::path::Path::new(&format!("{}/broken.html", out)).exists());
+
+    let output = akari(&["build", src], "");
+    assert_eq!(output.status.code(), Some(2));
+
+    // Building into a directory inside the site leaves the earlier output out, however it is named
+    fs::remove_file(format!("{}/broken.html", src)).unwrap();
+    for _ in 0..2 {
+        let output = Command::new(env!("CARGO_BIN_EXE_akari"))
+            .args(["build", ".", "out", "--data", "../site.json"])
+            .current_dir(src)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    assert!(std::path::Path::new(&format!("{}/out/index.html", src)).exists());
+    assert!(!std::path::Path::new(&format!("{}/out/out", src)).exists());
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "bin")]
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use akari::Value;
+
+/// Drives `akari-lsp` over stdio like an editor would
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_akari-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start akari-lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Client { child, stdin, stdout, next_id: 1 }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.into_json();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        Value::from_json(&String::from_utf8(body).unwrap()).unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(object([("jsonrpc", Value::new("2.0")), ("method", Value::new(method)), ("params", params)]));
+    }
+
+    /// Sends a request and returns its result, skipping notifications sent meanwhile
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(object([("jsonrpc", Value::new("2.0")), ("id", Value::new(id)), ("method", Value::new(method)), ("params", params)]));
+        loop {
+            let message = self.receive();
+            if message.get("id").integer() == id {
+                assert!(message.get("error").is_none(), "{} failed: {}", method, message.into_json());
+                return message.get("result").clone();
+            }
+        }
+    }
+
+    /// The diagnostics published next for `uri`
+    fn diagnostics(&mut self, uri: &str) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message.get("method").string() == "textDocument/publishDiagnostics" && message.get("params").get("uri").string() == uri {
+                return match message.get("params").get("diagnostics") {
+                    Value::List(diagnostics) => diagnostics.clone(),
+                    _ => Vec::new(),
+                };
+            }
+        }
+    }
+}
+
+fn object<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Dict(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect::<HashMap<_, _>>())
+}
+
+/// Text document position params for the first occurrence of `needle` in `text`, plus `shift`
+fn at(uri: &str, text: &str, needle: &str, shift: usize) -> Value {
+    let offset = text.find(needle).unwrap() + shift;
+    let line = text[..offset].matches('\n').count();
+    let character = offset - text[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+    object([
+        ("textDocument", object([("uri", Value::new(uri))])),
+        ("position", object([("line", Value::new(line)), ("character", Value::new(character))])),
+    ])
+}
+
+fn change(client: &mut Client, uri: &str, version: i64, text: &str) {
+    client.notify("textDocument/didChange", object([
+        ("textDocument", object([("uri", Value::new(uri)), ("version", Value::new(version))])),
+        ("contentChanges", Value::List(vec![object([("text", Value::new(text))])])),
+    ]));
+}
+
+#[test]
+fn test_language_server() {
+    let root = format!("{}/tests/templates", env!("CARGO_MANIFEST_DIR"));
+    let page_uri = format!("file://{}/page.html", root);
+    let base_uri = format!("file://{}/base.html", root);
+    let page = std::fs::read_to_string(format!("{}/page.html", root)).unwrap();
+
+    let mut client = Client::start();
+    let result = client.request("initialize", object([("rootUri", Value::new(format!("file://{}", root)))]));
+    assert!(result.get("capabilities").get("hoverProvider").boolean());
+    client.notify("initialized", object([]));
+
+    // Tokenizer and structure errors
+    let broken = "-[ template \"base.html\" ]-\n-[ block body ]--[ if name ]-hi-[ endblock ]-";
+    client.notify("textDocument/didOpen", object([("textDocument", object([
+        ("uri", Value::new(page_uri.as_str())),
+        ("languageId", Value::new("akari")),
+        ("version", Value::new(1)),
+        ("text", Value::new(broken)),
+    ]))]));
+    let diagnostics = client.diagnostics(&page_uri);
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0].get("message").string(), "`endblock` found while `if` is still open, expected `endif`");
+    assert_eq!(diagnostics[0].get("range").get("start").get("line").integer(), 1);
+
+    change(&mut client, &page_uri, 2, "-[ for x in items ]-\n-[ output \"oops ]-");
+    let messages: Vec<String> = client.diagnostics(&page_uri).iter().map(|d| d.get("message").string()).collect();
+    assert!(messages.contains(&"Unterminated string literal".to_string()), "{:?}", messages);
+    assert!(messages.contains(&"`for` is never closed, expected `endfor`".to_string()), "{:?}", messages);
+
+    // Analysis errors on the directive causing them
+    let missing = page.replace("partials/greeting.html", "partials/missing.html");
+    change(&mut client, &page_uri, 3, &missing);
+    let diagnostics = client.diagnostics(&page_uri);
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0].get("message").string(), "Template 'partials/missing.html' not found");
+    assert_eq!(diagnostics[0].get("range").get("start").get("character").integer(), 26);
+
+    change(&mut client, &page_uri, 4, &page);
+    assert!(client.diagnostics(&page_uri).is_empty());
+
+    // Definitions of template paths and blocks
+    let location = client.request("textDocument/definition", at(&page_uri, &page, "base.html", 2));
+    assert_eq!(location.get("uri").string(), base_uri);
+    let location = client.request("textDocument/definition", at(&page_uri, &page, "greeting", 0));
+    assert!(location.get("uri").string().ends_with("/partials/greeting.html"));
+    let locations = client.request("textDocument/definition", at(&page_uri, &page, "body", 1));
+    assert_eq!(locations.list()[0].get("uri").string(), base_uri);
+    assert_eq!(locations.list()[0].get("range").get("start").get("character").integer(), 15);
+
+    // The override chain of a block
+    let hover = client.request("textDocument/hover", at(&page_uri, &page, "body", 2));
+    let contents = hover.get("contents").get("value").string();
+    assert!(contents.contains("Defined in: `base.html` → `page.html`"), "{}", contents);
+    assert!(contents.contains("Rendered from `page.html`"), "{}", contents);
+
+    // The closer of the innermost open section comes first
+    let partial = "-[ block body ]--[ for x in xs ]--[ ";
+    change(&mut client, &page_uri, 5, partial);
+    client.diagnostics(&page_uri);
+    let items = client.request("textDocument/completion", at(&page_uri, partial, "-[ ", partial.len()));
+    let preselected: Vec<String> = items.list().iter()
+        .filter(|item| item.get("preselect").boolean())
+        .map(|item| item.get("label").string())
+        .collect();
+    assert_eq!(preselected, vec!["endfor".to_string()]);
+
+    let items = client.request("textDocument/completion", at(&page_uri, partial, "body", 0));
+    assert!(items.list().iter().any(|item| item.get("label").string() == "body"));
+
+    client.request("shutdown", Value::None);
+    client.notify("exit", Value::None);
+    assert!(client.child.wait().unwrap().success());
+}
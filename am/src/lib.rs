@@ -4,6 +4,8 @@ use syn::{parse_macro_input, Token, Ident, braced, bracketed};
 use syn::parse::{Parse, ParseStream, Result};
 use proc_macro2::TokenStream as TokenStream2;
 
+mod template;
+
 /// A macro to create an Value from a literal or expression.
 /// It can handle dictionaries, lists, booleans, strings, and numeric values. 
 #[proc_macro]
@@ -13,6 +15,31 @@ pub fn object(input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+/// Embeds a template file at compile time as a `&'static str`.
+/// The path is relative to the crate's `Cargo.toml`, and a missing file is a compile error.
+///
+/// Only the source is embedded; `TemplateManager` tokenizes it when it is rendered, like a
+/// file read from disk, so syntax errors show up in the render as they would for that file.
+#[proc_macro]
+pub fn template(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as syn::LitStr);
+    template::embed_template(&path)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Embeds every template under a directory at compile time as a
+/// `&'static [(&'static str, &'static str)]` of names and sources, like `template!`.
+/// Names are relative to the directory and use `/` separators, matching the names
+/// `TemplateManager` resolves templates by. Hidden files are skipped.
+#[proc_macro]
+pub fn include_templates(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as syn::LitStr);
+    template::embed_templates(&path)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
 // Define our custom syntax structures
 enum ValueExpr {
     Dict(Dict),
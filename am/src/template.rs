@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::LitStr;
+
+/// Expands `template!("path")` into a `&'static str` holding the source
+pub fn embed_template(path: &LitStr) -> syn::Result<TokenStream2> {
+    let file = resolve(path)?;
+    read_source(&file, path.span())?;
+
+    let file = file.to_string_lossy().into_owned();
+    Ok(quote! { include_str!(#file) })
+}
+
+/// Expands `include_templates!("dir/")` into a `&'static [(&'static str, &'static str)]`
+/// of template names relative to the directory and their sources
+pub fn embed_templates(path: &LitStr) -> syn::Result<TokenStream2> {
+    let dir = resolve(path)?;
+    if !dir.is_dir() {
+        return Err(syn::Error::new(path.span(), format!("{} is not a directory", dir.display())));
+    }
+
+    let mut files = Vec::new();
+    collect_files(&dir, &mut files).map_err(|e| {
+        syn::Error::new(path.span(), format!("Failed to read {}: {}", dir.display(), e))
+    })?;
+    files.sort();
+
+    let mut entries = Vec::new();
+    for file in files {
+        read_source(&file, path.span())?;
+
+        let name = file.strip_prefix(&dir).unwrap_or(&file)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        let file = file.to_string_lossy().into_owned();
+        entries.push(quote! { (#name, include_str!(#file)) });
+    }
+
+    Ok(quote! {
+        {
+            const TEMPLATES: &[(&str, &str)] = &[#(#entries),*];
+            TEMPLATES
+        }
+    })
+}
+
+/// Paths are relative to the directory of the crate invoking the macro
+fn resolve(path: &LitStr) -> syn::Result<PathBuf> {
+    let root = std::env::var("CARGO_MANIFEST_DIR").map_err(|_| {
+        syn::Error::new(path.span(), "CARGO_MANIFEST_DIR is not set")
+    })?;
+    Ok(Path::new(&root).join(path.value()))
+}
+
+/// Reads a template, so a missing or non-UTF-8 file is reported at the macro's path
+fn read_source(file: &Path, span: Span) -> syn::Result<String> {
+    fs::read_to_string(file).map_err(|e| {
+        syn::Error::new(span, format!("Failed to read template {}: {}", file.display(), e))
+    })
+}
+
+/// Recursively lists the files of a directory, skipping hidden entries
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}